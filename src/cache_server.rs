@@ -0,0 +1,173 @@
+//! Sharing one resolver's cache between processes over a unix socket.
+//!
+//! A long-running process calls [`serve`] to answer lookups from its
+//! [`HickoryDnsResolver`]; short-lived ones give a [`CacheServerClient`] to
+//! reqwest instead of their own resolver, so they all share its warm cache.
+//!
+//! The protocol is one line per lookup: the client sends the hostname, and
+//! the server answers `ok` followed by the addresses, or `err` followed by
+//! the reason, separated by spaces.
+
+use std::error::Error;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, info, warn};
+
+use crate::error::DnsCacheError;
+use crate::lookup_times::record_lookup_time;
+use crate::resolver::HickoryDnsResolver;
+
+/// How long a client made with [`CacheServerClient::new`] waits for the
+/// server before resolving locally. Long enough for the server's own
+/// upstream lookup with the default timeout and attempts.
+pub const SERVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Answers lookups on the unix socket at `path` from `resolver` until the
+/// returned future is dropped, e.g. on Ctrl-C.
+///
+/// A socket file left behind by a server that's no longer running is
+/// replaced; one that a live server is still listening on fails with
+/// [`DnsCacheError::Io`]. The socket file isn't removed when serving stops.
+pub async fn serve(resolver: HickoryDnsResolver, path: impl AsRef<Path>) -> Result<(), DnsCacheError> {
+    let path = path.as_ref();
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            let message = format!("a cache server is already listening on {}", path.display());
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, message).into());
+        }
+        debug!("Removing stale cache server socket {}", path.display());
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    info!("Serving DNS lookups on {}", path.display());
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let resolver = resolver.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer(&resolver, stream).await {
+                debug!("Cache server connection ended: {}", e);
+            }
+        });
+    }
+}
+
+// Answer every lookup a client sends on one connection
+async fn answer(resolver: &HickoryDnsResolver, stream: UnixStream) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(host) = lines.next_line().await? {
+        let response = match resolver.resolve_host(host.trim()).await {
+            Ok(ips) => {
+                let ips: Vec<String> = ips.iter().map(ToString::to_string).collect();
+                format!("ok {}\n", ips.join(" "))
+            }
+            // One line per response, whatever the error says
+            Err(e) => format!("err {}\n", e.to_string().replace('\n', " ")),
+        };
+        writer.write_all(response.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// A `reqwest::dns::Resolve` implementation that asks the cache server on a
+/// unix socket, falling back to a local resolver whenever the server can't
+/// be reached or doesn't answer in time, [`SERVER_TIMEOUT`] by default.
+///
+/// A host the server fails to resolve fails here too; it isn't retried
+/// locally, since the server already asked upstream.
+#[derive(Clone)]
+pub struct CacheServerClient {
+    socket: Arc<PathBuf>,
+    local: HickoryDnsResolver,
+    timeout: Duration,
+}
+
+impl CacheServerClient {
+    /// Creates a client for the server on `socket`, using `local` when the
+    /// server isn't available. Nothing is connected until the first lookup.
+    pub fn new(socket: impl Into<PathBuf>, local: HickoryDnsResolver) -> Self {
+        Self::with_timeout(socket, local, SERVER_TIMEOUT)
+    }
+
+    /// Like [`new`](Self::new), but waits only `timeout` for the server
+    /// before resolving locally. Keep it well below any overall request
+    /// timeout, or the request ends before the fallback gets to run.
+    pub fn with_timeout(socket: impl Into<PathBuf>, local: HickoryDnsResolver, timeout: Duration) -> Self {
+        CacheServerClient {
+            socket: Arc::new(socket.into()),
+            local,
+            timeout,
+        }
+    }
+
+    /// Resolves `host` through the server, or locally if it's unavailable.
+    /// The time taken either way goes to
+    /// [`record_lookup_time`](crate::record_lookup_time).
+    pub async fn resolve_host(&self, host: &str) -> Result<Vec<IpAddr>, DnsCacheError> {
+        let start = Instant::now();
+        let result = match tokio::time::timeout(self.timeout, self.ask_server(host)).await {
+            Ok(Ok(answer)) => {
+                debug!("Cache server resolved {} in {:?}", host, start.elapsed());
+                answer
+            }
+            Ok(Err(e)) => {
+                warn!("Cache server at {} unavailable ({}), resolving {} locally", self.socket.display(), e, host);
+                self.local.resolve_host(host).await
+            }
+            Err(_) => {
+                warn!("Cache server at {} timed out, resolving {} locally", self.socket.display(), host);
+                self.local.resolve_host(host).await
+            }
+        };
+        record_lookup_time(host, start.elapsed());
+        result
+    }
+
+    // The server's answer for `host`; the outer error means there was no
+    // usable answer, so the caller should resolve locally
+    async fn ask_server(&self, host: &str) -> io::Result<Result<Vec<IpAddr>, DnsCacheError>> {
+        let stream = UnixStream::connect(&*self.socket).await?;
+        let (reader, mut writer) = stream.into_split();
+        writer.write_all(format!("{}\n", host).as_bytes()).await?;
+
+        let mut response = String::new();
+        BufReader::new(reader).read_line(&mut response).await?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("invalid response {:?}", response));
+        let (status, rest) = response.trim_end().split_once(' ').unwrap_or((response.trim_end(), ""));
+        match status {
+            "ok" => {
+                let ips = rest
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<Vec<IpAddr>, _>>()
+                    .map_err(|_| invalid())?;
+                Ok(Ok(ips))
+            }
+            "err" => Ok(Err(DnsCacheError::CacheServer(rest.to_string()))),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl reqwest::dns::Resolve for CacheServerClient {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let this = self.clone();
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            match this.resolve_host(&host).await {
+                Ok(ips) => {
+                    let addrs: Vec<SocketAddr> = ips.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect();
+                    Ok(Box::new(addrs.into_iter()) as Box<dyn Iterator<Item = SocketAddr> + Send>)
+                }
+                Err(e) => Err(Box::new(e) as Box<dyn Error + Send + Sync>),
+            }
+        })
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+/// Per-host circuit breaker: after `threshold` consecutive failed lookups of
+/// a host, further lookups fail fast for `cooldown`. Then a single probe
+/// lookup is let through; if upstream answers it, even to say the name
+/// doesn't exist, the circuit closes again, if it fails it stays open for
+/// another `cooldown`.
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    pub(crate) threshold: u32,
+    pub(crate) cooldown: Duration,
+    hosts: Mutex<HashMap<String, Circuit>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Circuit {
+    /// Lookups go through. Failures only count as consecutive while each
+    /// comes within `cooldown` of the one before.
+    Closed { failures: u32, last_failure: Instant },
+    /// Lookups fail fast until the deadline.
+    Open { until: Instant },
+    /// One probe lookup is in flight; the rest still fail fast. A probe
+    /// that never reports back (e.g. it was cancelled) is replaced after
+    /// `cooldown`.
+    HalfOpen { probe_started: Instant },
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether a lookup of `host` may go ahead. If not, returns how long
+    /// until the next probe is allowed.
+    pub(crate) fn allow(&self, host: &str, now: Instant) -> Result<(), Duration> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let Some(circuit) = hosts.get_mut(host) else {
+            return Ok(());
+        };
+        match *circuit {
+            Circuit::Closed { .. } => Ok(()),
+            Circuit::Open { until } if now < until => Err(until - now),
+            Circuit::HalfOpen { probe_started } if now < probe_started + self.cooldown => {
+                Err(probe_started + self.cooldown - now)
+            }
+            Circuit::Open { .. } | Circuit::HalfOpen { .. } => {
+                info!(host, "Circuit half-open, probing upstream");
+                *circuit = Circuit::HalfOpen { probe_started: now };
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn record_success(&self, host: &str) {
+        let previous = self.hosts.lock().unwrap().remove(host);
+        if let Some(Circuit::HalfOpen { .. }) = previous {
+            info!(host, "Probe succeeded, circuit closed");
+        }
+    }
+
+    pub(crate) fn record_failure(&self, host: &str, now: Instant) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let failures = match hosts.get(host) {
+            Some(&Circuit::Closed { failures, last_failure }) if now - last_failure < self.cooldown => failures + 1,
+            // Finished after the circuit opened, so it started before
+            Some(Circuit::Open { .. }) => return,
+            // The probe failed, so straight back to open
+            Some(Circuit::HalfOpen { .. }) => self.threshold,
+            _ => 1,
+        };
+
+        let circuit = if failures >= self.threshold {
+            warn!(
+                host,
+                failures,
+                cooldown_ms = self.cooldown.as_millis() as u64,
+                "Circuit opened, failing lookups fast"
+            );
+            Circuit::Open { until: now + self.cooldown }
+        } else {
+            Circuit::Closed { failures, last_failure: now }
+        };
+        hosts.insert(host.to_string(), circuit);
+    }
+}
@@ -0,0 +1,37 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+/// Address ranges that resolution results must not fall into, e.g. to keep
+/// user-supplied URLs from reaching internal services (SSRF).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DenyList {
+    pub(crate) cidrs: Vec<IpNet>,
+    pub(crate) private: bool,
+}
+
+impl DenyList {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.cidrs.is_empty() && !self.private
+    }
+
+    /// Whether `ip` is in a denied range. IPv4-mapped IPv6 addresses are
+    /// checked as the IPv4 address they map to, so `::ffff:10.0.0.1` can't
+    /// slip past a `10.0.0.0/8` entry.
+    pub(crate) fn denies(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        (self.private && is_private(ip)) || self.cidrs.iter().any(|cidr| cidr.contains(&ip))
+    }
+}
+
+// Addresses that only make sense inside a host or network: loopback,
+// unspecified, link-local (which includes cloud metadata endpoints such as
+// 169.254.169.254), RFC 1918 and IPv6 unique local
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_unspecified() || ip.is_link_local() || ip.is_private(),
+        IpAddr::V6(ip) => {
+            ip.is_loopback() || ip.is_unspecified() || ip.is_unicast_link_local() || ip.is_unique_local()
+        }
+    }
+}
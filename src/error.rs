@@ -0,0 +1,43 @@
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use hickory_resolver::error::ResolveError;
+
+/// Errors returned by the crate's public helpers.
+#[derive(Debug, thiserror::Error)]
+pub enum DnsCacheError {
+    /// A hostname couldn't be resolved.
+    #[error("DNS resolution failed: {0}")]
+    Resolve(#[from] ResolveError),
+    /// An answer failed DNSSEC validation, so it can't be trusted.
+    #[error("DNSSEC validation failed: {0}")]
+    DnssecValidation(ResolveError),
+    /// A lookup didn't finish within the deadline the caller gave it.
+    #[error("DNS resolution of {host} timed out after {deadline:?}")]
+    Timeout { host: String, deadline: Duration },
+    /// Every address a host resolved to is in a denied range.
+    #[error("all addresses of {host} are blocked: {addrs:?}")]
+    Blocked { host: String, addrs: Vec<IpAddr> },
+    /// A hostname isn't ASCII and couldn't be converted to punycode.
+    #[error("invalid hostname {host:?}: not a valid internationalized domain name")]
+    InvalidHostname { host: String },
+    /// A name was rejected by strict mode for not being fully qualified.
+    #[error("{host:?} is not a fully-qualified domain name")]
+    NotFullyQualified { host: String },
+    /// An HTTP request failed.
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// A cache server couldn't resolve a host; this is the reason it gave.
+    #[error("cache server failed to resolve: {0}")]
+    CacheServer(String),
+    /// A response body was bigger than the caller was willing to read.
+    #[error("response body exceeds the limit of {limit} bytes")]
+    BodyTooLarge { limit: u64 },
+    /// Reading a file or writing output failed.
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    /// The resolver was configured with something it can't use.
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+}
@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+/// How many events a subscriber can fall behind by before it starts missing
+/// the oldest ones, unless set with
+/// [`event_capacity`](crate::HickoryDnsResolverBuilder::event_capacity).
+pub(crate) const DEFAULT_EVENT_CAPACITY: usize = 1024;
+
+/// One lookup, as received from
+/// [`HickoryDnsResolver::subscribe`](crate::HickoryDnsResolver::subscribe).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolveEvent {
+    /// The hostname as it was looked up.
+    pub host: String,
+    /// How long the lookup took.
+    pub duration: Duration,
+    /// How the lookup ended.
+    pub outcome: ResolveOutcome,
+    /// Addresses in the answer, before any IP strategy filtering; zero
+    /// unless it succeeded.
+    pub addr_count: usize,
+}
+
+/// How a lookup ended; the same as the `outcome` field of its log event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResolveOutcome {
+    /// Answered from the cache.
+    Hit,
+    /// Answered by upstream.
+    Miss,
+    /// Upstream couldn't answer, though a stale answer may still have been
+    /// served.
+    Failure,
+    /// Dropped before it finished, e.g. because the caller timed out.
+    Cancelled,
+}
+
+/// Sends an event to every subscriber, if there are any. Never blocks: a
+/// subscriber that falls too far behind misses the oldest events instead.
+pub(crate) fn emit(
+    events: &broadcast::Sender<ResolveEvent>,
+    host: &str,
+    duration: Duration,
+    outcome: ResolveOutcome,
+    addr_count: usize,
+) {
+    // Skip building the event when nobody is listening
+    if events.receiver_count() == 0 {
+        return;
+    }
+    let _ = events.send(ResolveEvent { host: host.to_string(), duration, outcome, addr_count });
+}
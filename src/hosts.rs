@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use tracing::debug;
+
+/// Static hostname-to-address mappings loaded from hosts-format files.
+///
+/// Hostnames are matched case-insensitively. A name listed on several lines,
+/// or in several files, maps to all of the addresses it was given.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct StaticHosts {
+    hosts: HashMap<String, Vec<IpAddr>>,
+}
+
+impl StaticHosts {
+    /// Parses `contents` in `/etc/hosts` format and adds its mappings.
+    ///
+    /// Each line is an address followed by one or more hostnames; `#` starts
+    /// a comment. Nothing is added if any line has an invalid address or no
+    /// hostname; the error names the first such line.
+    pub(crate) fn merge(&mut self, contents: &str) -> Result<(), String> {
+        let mut entries = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(addr) = fields.next() else {
+                continue;
+            };
+            let ip = addr
+                .parse::<IpAddr>()
+                .map_err(|_| format!("line {}: invalid address `{}`", number + 1, addr))?;
+            let names: Vec<&str> = fields.collect();
+            if names.is_empty() {
+                return Err(format!("line {}: no hostname for {}", number + 1, ip));
+            }
+            entries.push((ip, names));
+        }
+
+        for (ip, names) in entries {
+            for name in names {
+                let ips = self.hosts.entry(normalize(name)).or_default();
+                if !ips.contains(&ip) {
+                    ips.push(ip);
+                }
+            }
+        }
+        debug!("Loaded {} static host mappings", self.hosts.len());
+        Ok(())
+    }
+
+    /// The configured addresses for `host`, if it has any.
+    pub(crate) fn get(&self, host: &str) -> Option<&[IpAddr]> {
+        self.hosts.get(&normalize(host)).map(Vec::as_slice)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+}
+
+// `Example.COM.` and `example.com` name the same host
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
@@ -0,0 +1,126 @@
+use std::net::{IpAddr, UdpSocket};
+
+use hickory_resolver::config::LookupIpStrategy;
+use tracing::debug;
+
+/// Which address families the resolver returns, and in what order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IpStrategy {
+    /// Only IPv4 (A) addresses.
+    Ipv4Only,
+    /// Only IPv6 (AAAA) addresses.
+    Ipv6Only,
+    /// IPv4 addresses, falling back to IPv6 if there are none. This is
+    /// hickory's default.
+    #[default]
+    Ipv4ThenIpv6,
+    /// IPv6 addresses, falling back to IPv4 if there are none.
+    Ipv6ThenIpv4,
+    /// Both families, alternating IPv6 and IPv4 (RFC 8305 "happy
+    /// eyeballs"), so a connector working down the list tries both early.
+    HappyEyeballs,
+}
+
+impl IpStrategy {
+    /// The hickory lookup strategy that queries the matching record types.
+    pub(crate) fn lookup_strategy(self) -> LookupIpStrategy {
+        match self {
+            IpStrategy::Ipv4Only => LookupIpStrategy::Ipv4Only,
+            IpStrategy::Ipv6Only => LookupIpStrategy::Ipv6Only,
+            IpStrategy::Ipv4ThenIpv6 => LookupIpStrategy::Ipv4thenIpv6,
+            IpStrategy::Ipv6ThenIpv4 => LookupIpStrategy::Ipv6thenIpv4,
+            IpStrategy::HappyEyeballs => LookupIpStrategy::Ipv4AndIpv6,
+        }
+    }
+
+    /// Drops addresses of the wrong family and puts the preferred family
+    /// first. hickory already applies the strategy to the queries it sends,
+    /// but answers from the hosts file aren't filtered by it.
+    pub(crate) fn apply<T>(self, items: &mut Vec<T>, ip_of: impl Fn(&T) -> IpAddr) {
+        match self {
+            IpStrategy::Ipv4Only => items.retain(|item| ip_of(item).is_ipv4()),
+            IpStrategy::Ipv6Only => items.retain(|item| ip_of(item).is_ipv6()),
+            // Stable sort keeps hickory's order within each family
+            IpStrategy::Ipv4ThenIpv6 => items.sort_by_key(|item| ip_of(item).is_ipv6()),
+            IpStrategy::Ipv6ThenIpv4 => items.sort_by_key(|item| ip_of(item).is_ipv4()),
+            IpStrategy::HappyEyeballs => interleave(items, ip_of),
+        }
+    }
+}
+
+// Reorders `items` as IPv6, IPv4, IPv6, ..., keeping the order within each
+// family; whichever family runs out first leaves the rest of the other at
+// the end.
+fn interleave<T>(items: &mut Vec<T>, ip_of: impl Fn(&T) -> IpAddr) {
+    let (v6, v4): (Vec<T>, Vec<T>) = items.drain(..).partition(|item| ip_of(item).is_ipv6());
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => items.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Which address families this host currently has a route for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct RoutableFamilies {
+    pub(crate) ipv4: bool,
+    pub(crate) ipv6: bool,
+}
+
+impl RoutableFamilies {
+    /// Checks for a route to a public address of each family. Connecting a
+    /// UDP socket only consults the routing table; nothing is sent.
+    pub(crate) fn probe() -> Self {
+        let routable = |local: &str, remote: &str| {
+            UdpSocket::bind(local)
+                .and_then(|socket| socket.connect(remote))
+                .is_ok()
+        };
+        let families = RoutableFamilies {
+            ipv4: routable("0.0.0.0:0", "8.8.8.8:53"),
+            ipv6: routable("[::]:0", "[2001:4860:4860::8888]:53"),
+        };
+        debug!("Routable address families: {:?}", families);
+        families
+    }
+
+    pub(crate) fn allows(self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(_) => self.ipv4,
+            IpAddr::V6(_) => self.ipv6,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ips(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn interleave_alternates_families_starting_with_ipv6() {
+        let mut items = ips(&["192.0.2.1", "192.0.2.2", "2001:db8::1", "192.0.2.3", "2001:db8::2"]);
+        interleave(&mut items, |ip| *ip);
+        assert_eq!(items, ips(&["2001:db8::1", "192.0.2.1", "2001:db8::2", "192.0.2.2", "192.0.2.3"]));
+    }
+
+    #[test]
+    fn interleave_keeps_a_single_family_in_order() {
+        let mut v4 = ips(&["192.0.2.2", "192.0.2.1"]);
+        interleave(&mut v4, |ip| *ip);
+        assert_eq!(v4, ips(&["192.0.2.2", "192.0.2.1"]));
+
+        let mut v6 = ips(&["2001:db8::2", "2001:db8::1"]);
+        interleave(&mut v6, |ip| *ip);
+        assert_eq!(v6, ips(&["2001:db8::2", "2001:db8::1"]));
+
+        let mut empty = Vec::new();
+        interleave(&mut empty, |ip| *ip);
+        assert!(empty.is_empty());
+    }
+}
@@ -0,0 +1,39 @@
+//! DNS caching for reqwest, backed by hickory-resolver.
+//!
+//! The main entry point is [`HickoryDnsResolver`], which plugs into
+//! `reqwest::ClientBuilder::dns_resolver` and keeps resolved answers in
+//! hickory's in-memory cache so repeated requests to the same host skip
+//! the network round-trip.
+
+#[cfg(unix)]
+pub mod cache_server;
+mod circuit;
+mod deny;
+mod error;
+mod events;
+mod hosts;
+mod ip_strategy;
+mod lookup_times;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+mod persist;
+mod records;
+mod resolver;
+mod stats;
+#[cfg(feature = "test-util")]
+pub mod testing;
+mod upstream;
+
+pub use error::DnsCacheError;
+pub use events::{ResolveEvent, ResolveOutcome};
+pub use ip_strategy::IpStrategy;
+pub use lookup_times::{record_lookup_time, with_lookup_times};
+pub use resolver::{HickoryDnsResolver, HickoryDnsResolverBuilder, DEFAULT_TTL, PREFETCH_CONCURRENCY};
+pub use records::SrvRecord;
+pub use stats::{CacheEntry, CacheStats, LatencySummary, LATENCY_WINDOW};
+pub use upstream::{ConfigSource, DnsProtocol, UpstreamDns};
+
+pub use hickory_resolver::error::ResolveError;
+pub use hickory_resolver::TokioAsyncResolver;
+pub use ipnet::IpNet;
+pub use hickory_resolver::proto::rr::{Record, RecordType};
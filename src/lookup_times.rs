@@ -0,0 +1,33 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+
+tokio::task_local! {
+    static LOOKUP_TIMES: RefCell<HashMap<String, Duration>>;
+}
+
+/// Runs `future`, also returning how long the latest lookup of each host
+/// took among those a resolver made while polling it.
+///
+/// reqwest calls the resolver deep inside its connector, so this is how a
+/// caller gets the DNS time of one request out, even while other requests
+/// to the same host run concurrently. A host that's missing wasn't resolved
+/// for this request, e.g. because it reused a pooled connection. Lookups in
+/// tasks `future` spawns aren't seen.
+pub async fn with_lookup_times<F: Future>(future: F) -> (F::Output, HashMap<String, Duration>) {
+    LOOKUP_TIMES
+        .scope(RefCell::new(HashMap::new()), async move {
+            let output = future.await;
+            (output, LOOKUP_TIMES.with(RefCell::take))
+        })
+        .await
+}
+
+/// Records that a lookup of `host` took `duration`, for the enclosing
+/// [`with_lookup_times`]; does nothing outside one. The resolvers in this
+/// crate call it for every lookup, and custom `reqwest::dns::Resolve`
+/// implementations can too.
+pub fn record_lookup_time(host: &str, duration: Duration) {
+    let _ = LOOKUP_TIMES.try_with(|times| times.borrow_mut().insert(host.to_string(), duration));
+}
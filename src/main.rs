@@ -1,144 +1,2300 @@
-use reqwest::Client;
-use std::error::Error;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, Client, ClientBuilder, Proxy, Response, StatusCode, Url};
+use std::collections::{BTreeMap, VecDeque};
 use std::fs::OpenOptions;
+use std::io::{self, IsTerminal};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use chrono::Utc;
-use hickory_resolver::TokioAsyncResolver;
-use tracing::{debug, info, instrument};
-use tracing_subscriber;
+#[cfg(unix)]
+use dns_query_caching::cache_server::{self, CacheServerClient};
+use dns_query_caching::{
+    record_lookup_time, with_lookup_times, CacheStats, DnsCacheError, DnsProtocol, HickoryDnsResolver, IpNet,
+    LatencySummary, RecordType, UpstreamDns,
+};
+use futures::future::join_all;
+use futures::StreamExt;
+use rand::Rng;
+use serde_json::json;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, instrument, warn, Instrument};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_appender::non_blocking::{NonBlocking, NonBlockingBuilder, WorkerGuard};
 use tracing_subscriber::fmt::Layer;
 use tracing_subscriber::prelude::*;
-use std::net::SocketAddr;
+use uuid::Uuid;
 
-// Custom DNS resolver that wraps hickory-resolver
-#[derive(Clone)]
-struct HickoryDnsResolver {
-    resolver: TokioAsyncResolver,
-}
-
-impl HickoryDnsResolver {
-    fn new() -> Self {
-        // Create custom resolver options with optimized caching
-        let mut opts = hickory_resolver::config::ResolverOpts::default();
-        opts.cache_size = 1024; // Increase cache size
-        opts.use_hosts_file = true;
-        opts.timeout = Duration::from_secs(3); // Reduce timeout from default
-        opts.attempts = 2; // Reduce retry attempts
-        
-        let resolver = TokioAsyncResolver::tokio(
-            hickory_resolver::config::ResolverConfig::default(),
-            opts,
-        );
-        
-        HickoryDnsResolver { resolver }
+/// Header carrying the per-request correlation ID.
+const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Largest response body `--read-body` downloads, unless `--max-body-bytes`
+/// says otherwise: 10 MiB.
+const DEFAULT_MAX_BODY_BYTES: u64 = 10 * 1024 * 1024;
+
+/// User-Agent sent unless `--user-agent` says otherwise.
+const DEFAULT_USER_AGENT: &str = concat!("dns_query_caching/", env!("CARGO_PKG_VERSION"));
+
+/// Measure DNS caching by repeatedly requesting a URL through a caching resolver.
+#[derive(Parser, Debug)]
+#[command(name = "dns_query_caching", version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// URL to request; repeat to cycle through several, listing a URL more
+    /// than once to give it a bigger share of the requests
+    #[arg(
+        long = "url",
+        value_name = "URL",
+        env = "DNS_CACHE_URL",
+        default_value = "https://google.com",
+        value_parser = parse_url
+    )]
+    urls: Vec<Url>,
+
+    /// Number of requests to make
+    #[arg(long, default_value_t = 5)]
+    count: u32,
+
+    /// Make this many warm-up requests first, one at a time, and report them
+    /// separately, so the cold lookup doesn't skew the `--count` measured
+    /// requests' statistics
+    #[arg(long, value_name = "N", default_value_t = 0, conflicts_with_all = ["hosts_file", "dry_run", "emit_hosts"])]
+    warmup: u32,
+
+    /// Number of requests to run in parallel
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    concurrency: u32,
+
+    /// Resolve every hostname in this file, one per line, instead of
+    /// requesting `--url`; `--concurrency` of them at a time
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["count", "watch"])]
+    hosts_file: Option<PathBuf>,
+
+    /// With `--hosts-file`, also request each host, at `--url` with its host
+    /// swapped for the one from the file
+    #[arg(long, requires = "hosts_file")]
+    fetch: bool,
+
+    /// Only resolve the host of each `--url`, printing whether it resolved,
+    /// without making any HTTP request; exits non-zero if any fails
+    #[arg(long, conflicts_with_all = ["hosts_file", "watch"])]
+    dry_run: bool,
+
+    /// Only resolve the host of each `--url`, or each host in
+    /// `--hosts-file`, and print the results in hosts-file format
+    /// (`1.2.3.4 example.com`); exits non-zero if any fails
+    #[arg(long, conflicts_with_all = ["watch", "dry_run", "compare", "fetch", "expect_status", "dns_slo"])]
+    emit_hosts: bool,
+
+    /// With `--emit-hosts`, print a line for every address of each host
+    /// instead of only the first
+    #[arg(long, requires = "emit_hosts")]
+    all_addresses: bool,
+
+    /// Run the requests twice, through the caching resolver and then through
+    /// the system's uncached one, and print their timings side by side
+    #[arg(long, conflicts_with_all = ["hosts_file", "watch", "dry_run", "pace"])]
+    compare: bool,
+
+    /// Keep requesting until interrupted instead of stopping after `--count`
+    #[arg(long, conflicts_with = "count")]
+    watch: bool,
+
+    /// Stop at the first failed request instead of logging it and carrying on
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Exit non-zero if any request gets a status other than these, e.g.
+    /// `200` or `200,301,302`
+    #[arg(
+        long,
+        value_name = "CODES",
+        value_delimiter = ',',
+        value_parser = parse_status,
+        conflicts_with_all = ["hosts_file", "dry_run", "compare"]
+    )]
+    expect_status: Vec<StatusCode>,
+
+    /// DNS latency budget, e.g. `50ms`: warn about each lookup that takes
+    /// longer, and report how many did at the end
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_interval,
+        conflicts_with_all = ["hosts_file", "dry_run", "compare"]
+    )]
+    dns_slo: Option<Duration>,
+
+    /// Time between rounds in `--watch` mode, e.g. `500ms`, `5s` or `1m`
+    #[arg(long, requires = "watch", value_parser = parse_interval)]
+    interval: Option<Duration>,
+
+    /// Delay between requests (or rounds of concurrent requests), in
+    /// milliseconds; 0 sends them back to back
+    #[arg(long, default_value_t = 100)]
+    delay_ms: u64,
+
+    /// How to choose the wait between rounds of requests
+    #[arg(long, value_enum, default_value_t = Pace::Fixed)]
+    pace: Pace,
+
+    /// Extra request header as `Name: value`; may be repeated
+    #[arg(long = "header", short = 'H', value_name = "NAME: VALUE", value_parser = parse_header)]
+    headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// User-Agent to send; some endpoints reject reqwest's default outright.
+    /// A `--header` with a User-Agent takes precedence
+    #[arg(long, value_name = "STRING", default_value = DEFAULT_USER_AGENT)]
+    user_agent: String,
+
+    /// Send requests through this HTTP(S) proxy; only the proxy's hostname
+    /// is then resolved by our resolver
+    #[arg(long, value_name = "URL", value_parser = parse_url)]
+    proxy: Option<Url>,
+
+    /// Refuse to connect to loopback, link-local (including the cloud
+    /// metadata address 169.254.169.254), private and unique local
+    /// addresses, whether resolved, in a URL or redirected to
+    #[arg(long)]
+    deny_private: bool,
+
+    /// Refuse to connect to addresses in these ranges, e.g.
+    /// `100.64.0.0/10`, like `--deny-private` does
+    #[arg(long, value_name = "CIDR", value_delimiter = ',', value_parser = parse_cidr)]
+    deny_cidr: Vec<IpNet>,
+
+    /// Accept any TLS certificate, including self-signed and expired ones.
+    /// Only for testing against servers you control
+    #[arg(long)]
+    insecure: bool,
+
+    /// Also trust the PEM-encoded root certificate in this file, e.g. a
+    /// staging environment's private CA
+    #[arg(long, value_name = "PATH")]
+    ca_cert: Option<PathBuf>,
+
+    /// Download the full response body and report its size and download time
+    #[arg(long)]
+    read_body: bool,
+
+    /// With `--read-body`, abort a request whose body grows past this many
+    /// bytes instead of buffering all of it
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_BODY_BYTES)]
+    max_body_bytes: u64,
+
+    /// Ask for gzip or brotli responses; with `--read-body`, compressed
+    /// bodies are also decompressed to report both sizes
+    #[cfg(feature = "compression")]
+    #[arg(long)]
+    compression: bool,
+
+    /// Retry a request this many times if it fails to resolve or connect
+    #[arg(long, default_value_t = 0)]
+    max_retries: u32,
+
+    /// Backoff before the first retry, in milliseconds; doubles on each retry
+    #[arg(long, default_value_t = 100)]
+    retry_backoff_ms: u64,
+
+    /// Randomize each retry backoff by up to this fraction either way, so
+    /// concurrent requests don't retry in lockstep; 0.2 means 80% to 120%
+    #[arg(long, value_name = "FRACTION", default_value_t = 0.0, value_parser = parse_jitter)]
+    retry_jitter: f64,
+
+    /// Timeout for a single DNS query, in milliseconds
+    #[arg(long, value_name = "MS", env = "DNS_CACHE_TIMEOUT", default_value_t = 3000)]
+    dns_timeout: u64,
+
+    /// Public name servers to send DNS queries to; the default is Google
+    #[arg(long, value_enum, env = "DNS_CACHE_UPSTREAM")]
+    upstream: Option<Upstream>,
+
+    /// Send DNS queries to this name server, as `IP:PORT` (port 53 if left
+    /// out), instead of Google public DNS; e.g. a local dnsmasq. Its
+    /// environment variable is ignored when `--upstream` is given
+    #[arg(long, value_name = "IP:PORT", env = "DNS_CACHE_SERVER", value_parser = parse_dns_server)]
+    dns_server: Option<SocketAddr>,
+
+    /// Transport for queries to `--dns-server`
+    #[arg(long, value_enum, default_value_t = DnsTransport::Udp, requires = "dns_server")]
+    dns_protocol: DnsTransport,
+
+    /// Maximum number of DNS answers to cache; set it below the number of
+    /// distinct hosts to see evictions
+    #[arg(long, value_name = "N", env = "DNS_CACHE_SIZE")]
+    dns_cache_size: Option<usize>,
+
+    /// Log where each lookup's time went: waiting for a slot, the hickory
+    /// query end to end, and local processing
+    #[arg(long)]
+    dns_timing_breakdown: bool,
+
+    /// Overall timeout for an HTTP request, DNS included, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = 10000)]
+    request_timeout: u64,
+
+    /// Close pooled connections idle for this long, in milliseconds
+    /// (reqwest's default is 90 seconds)
+    #[arg(long, value_name = "MS")]
+    pool_idle_timeout: Option<u64>,
+
+    /// Keep at most this many idle connections per host; 0 forces a fresh
+    /// connection, and so a DNS lookup, for every request
+    #[arg(long, value_name = "N")]
+    pool_max_idle_per_host: Option<usize>,
+
+    /// Set TCP_NODELAY on connections, `true` or `false` (reqwest's default
+    /// is `true`)
+    #[arg(long, value_name = "BOOL")]
+    tcp_nodelay: Option<bool>,
+
+    /// Enable TCP keep-alive probes after this much idle time, e.g. `30s`
+    /// (off by default)
+    #[arg(long, value_name = "DURATION", value_parser = parse_interval)]
+    tcp_keepalive: Option<Duration>,
+
+    /// HTTP version to speak to the server
+    #[arg(long, value_enum, default_value_t = HttpVersion::Auto)]
+    http_version: HttpVersion,
+
+    /// Write logs to this file instead of a new timestamped file per run
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Write logs to stdout instead of a file
+    #[arg(long, conflicts_with = "log_file")]
+    log_stdout: bool,
+
+    /// Format of the log lines
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Lowest level to log, e.g. `info`; RUST_LOG, if set, overrides it
+    /// unless this is given on the command line
+    #[arg(long, value_name = "LEVEL", env = "DNS_CACHE_LOG_LEVEL")]
+    log_level: Option<LevelFilter>,
+
+    /// Whether `--log-level` was given on the command line rather than by
+    /// its environment variable
+    #[arg(skip)]
+    log_level_from_flag: bool,
+
+    /// Log per-request lines at debug level, hidden unless RUST_LOG asks for
+    /// them, leaving only the final summary
+    #[arg(long)]
+    summary_only: bool,
+
+    /// How to print the results once all requests finish
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Load cached DNS answers from this file at startup and save them back
+    /// on exit, so the cache stays warm across runs
+    #[arg(long, value_name = "PATH")]
+    cache_file: Option<PathBuf>,
+
+    /// Serve this process's DNS cache to other invocations on a unix socket
+    /// at this path until interrupted, instead of making requests
+    #[cfg(unix)]
+    #[arg(
+        long,
+        value_name = "SOCKET",
+        conflicts_with_all = ["use_cache_server", "dry_run", "emit_hosts", "compare", "hosts_file", "watch"]
+    )]
+    cache_server: Option<PathBuf>,
+
+    /// Resolve through the `--cache-server` listening on this unix socket,
+    /// falling back to our own resolver whenever it isn't available
+    #[cfg(unix)]
+    #[arg(long, value_name = "SOCKET", conflicts_with_all = ["dry_run", "emit_hosts", "compare", "hosts_file"])]
+    use_cache_server: Option<PathBuf>,
+
+    /// Also export tracing spans to this OTLP/HTTP collector, e.g.
+    /// `http://localhost:4318`; `/v1/traces` is added if the URL has no path
+    #[cfg(feature = "otel")]
+    #[arg(long, value_name = "URL", value_parser = parse_url)]
+    otlp_endpoint: Option<Url>,
+}
+
+/// Alternatives to the default HTTP benchmark.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Resolve a hostname through the caching resolver without making any
+    /// HTTP request, showing how the cache warms up
+    Resolve {
+        /// Hostname to resolve
+        host: String,
+
+        /// Number of times to resolve it
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+        repeat: u32,
+
+        /// Bypass the cache so every lookup goes upstream
+        #[arg(long)]
+        no_cache: bool,
+
+        /// Also look up the PTR names of the resolved addresses
+        #[arg(long)]
+        ptr: bool,
+    },
+    /// Resolve a hostname several times back to back and print each lookup
+    /// time as CSV, to plot the drop from the cold first lookup to warm ones
+    WarmupCurve {
+        /// Hostname to resolve
+        host: String,
+
+        /// Number of lookups
+        #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+        count: u32,
+    },
+    /// Resolve a hostname upstream on a timer, bypassing the cache, and log
+    /// a rolling p50/p99 of the lookup times until interrupted
+    Probe {
+        /// Hostname to resolve
+        host: String,
+
+        /// Time between lookups, e.g. `500ms`, `5s` or `1m`
+        #[arg(long, default_value = "5s", value_parser = parse_interval)]
+        interval: Duration,
+
+        /// Number of latest lookups the percentiles are computed over
+        #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+        window: u32,
+
+        /// Stop after this many lookups instead of running until interrupted
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        count: Option<u32>,
+    },
+    /// Resolve more distinct hostnames than the cache holds, then re-resolve
+    /// the earliest ones to check they were evicted and fetched again;
+    /// exits non-zero if any was still cached
+    CachePressure {
+        /// How many hosts to resolve beyond the cache size; the earliest this
+        /// many are re-resolved
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+        extra: u32,
+
+        /// Pattern for the generated hostnames, with `{}` replaced by a
+        /// number; it needs wildcard DNS, which the default gets from nip.io
+        #[arg(long, default_value = "{}.127.0.0.1.nip.io", value_parser = parse_host_template)]
+        template: String,
+    },
+    /// Look up the SRV records of a service, e.g. `_http._tcp.example.com`
+    Srv {
+        /// Service name to look up
+        name: String,
+    },
+    /// Look up the TXT records of a name
+    Txt {
+        /// Name to look up
+        name: String,
+    },
+    /// Look up records of any type, e.g. `query example.com MX`
+    Query {
+        /// Name to look up
+        name: String,
+
+        /// Record type, such as MX, NS, CAA or SOA
+        #[arg(value_parser = parse_record_type)]
+        record_type: RecordType,
+    },
+    /// Show the chain of CNAMEs a hostname resolves through
+    Cname {
+        /// Hostname to trace
+        host: String,
+    },
+}
+
+/// How long to wait between rounds of requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Pace {
+    /// `--delay-ms`, or `--interval` in watch mode
+    Fixed,
+    /// Wait half the remaining TTL of the cached answer each round, closing
+    /// in on its expiry, then wait until just past it so the next lookup
+    /// goes upstream; falls back to the fixed wait when nothing is cached
+    TtlAware,
+}
+
+/// How far from expiry `Pace::TtlAware` stops halving, and how long past
+/// expiry it then waits.
+const TTL_PACE_MARGIN: Duration = Duration::from_millis(100);
+
+/// Public name servers selectable with `--upstream`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Upstream {
+    /// The name servers from the OS configuration
+    System,
+    /// Cloudflare (1.1.1.1)
+    Cloudflare,
+    /// Google (8.8.8.8)
+    Google,
+    /// Quad9 (9.9.9.9)
+    Quad9,
+}
+
+impl From<Upstream> for UpstreamDns {
+    fn from(upstream: Upstream) -> Self {
+        match upstream {
+            Upstream::System => UpstreamDns::System,
+            Upstream::Cloudflare => UpstreamDns::Cloudflare,
+            Upstream::Google => UpstreamDns::Google,
+            Upstream::Quad9 => UpstreamDns::Quad9,
+        }
     }
 }
 
-// Custom trait implementation for reqwest DNS resolution
-impl reqwest::dns::Resolve for HickoryDnsResolver {
-    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
-        let resolver = self.resolver.clone();
-        let host = name.as_str().to_string();
-        
-        Box::pin(async move {
-            let start = Instant::now();
-            debug!("Resolving hostname: {}", host);
-            
-            match resolver.lookup_ip(host.as_str()).await {
-                Ok(lookup) => {
-                    let addrs: Vec<SocketAddr> = lookup
-                        .iter()
-                        .map(|ip| SocketAddr::new(ip, 0))
-                        .collect();
-                    
-                    let duration = start.elapsed();
-                    info!("DNS resolution for {} took {:?}", host, duration);
-                    debug!("Resolved {} to {} addresses", host, addrs.len());
-                    
-                    Ok(Box::new(addrs.into_iter()) as Box<dyn Iterator<Item = SocketAddr> + Send>)
-                },
-                Err(e) => {
-                    info!("Failed to resolve {}: {}", host, e);
-                    Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::NotFound,
-                        format!("DNS resolution failed: {}", e),
-                    )) as Box<dyn Error + Send + Sync>)
-                }
-            }
-        })
+/// How queries reach `--dns-server`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum DnsTransport {
+    /// UDP, retrying over TCP when an answer is truncated
+    Udp,
+    /// TCP only
+    Tcp,
+}
+
+/// Which HTTP version the client uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum HttpVersion {
+    /// Let reqwest negotiate (HTTP/2 via ALPN over TLS, otherwise HTTP/1.1)
+    Auto,
+    /// HTTP/1.1 only
+    Http1,
+    /// HTTP/2 without negotiation, for servers known to support it
+    Http2,
+}
+
+/// How log lines are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, with event fields as queryable keys
+    Json,
+}
+
+/// Result format printed to stdout after the run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable summary lines
+    Text,
+    /// A single JSON object with per-request timings and aggregate stats
+    Json,
+}
+
+// Logs a per-request line at info level, or at debug level when `quiet` so
+// `--summary-only` runs keep the log down to the summary
+macro_rules! per_request {
+    ($quiet:expr, $($arg:tt)+) => {
+        if $quiet {
+            debug!($($arg)+)
+        } else {
+            info!($($arg)+)
+        }
+    };
+}
+
+// Logs are written on a background thread. Unlike `non_blocking`'s default,
+// a burst that fills its buffer makes logging wait rather than drop lines.
+fn log_writer<W: io::Write + Send + 'static>(writer: W) -> (NonBlocking, WorkerGuard) {
+    NonBlockingBuilder::default().lossy(false).finish(writer)
+}
+
+// Environment variables fill in options that weren't given as flags, and a
+// flag on the command line beats any of them, including variables that
+// would otherwise override it: `--upstream` wins over DNS_CACHE_SERVER, and
+// `--log-level` over RUST_LOG.
+fn parse_args() -> Args {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let from_flag = |id| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+    if from_flag("upstream") {
+        if from_flag("dns_server") {
+            Args::command()
+                .error(ErrorKind::ArgumentConflict, "--upstream and --dns-server can't be used together")
+                .exit();
+        }
+        args.dns_server = None;
+    }
+    args.log_level_from_flag = from_flag("log_level");
+    args
+}
+
+// Honor `level` if it was given as a flag, then RUST_LOG, then `level` from
+// its environment variable, defaulting to DEBUG when none is set. With
+// `summary_only` the default is INFO instead, and the resolver's per-lookup
+// lines are hidden too.
+fn log_filter(summary_only: bool, level: Option<LevelFilter>, level_from_flag: bool) -> EnvFilter {
+    if !level_from_flag && std::env::var_os(EnvFilter::DEFAULT_ENV).is_some() {
+        return EnvFilter::builder()
+            .with_default_directive(LevelFilter::DEBUG.into())
+            .from_env_lossy();
+    }
+    if summary_only {
+        let level = level.unwrap_or(LevelFilter::INFO);
+        return EnvFilter::new(format!("{},dns_query_caching::resolver=warn", level));
+    }
+    EnvFilter::new(level.unwrap_or(LevelFilter::DEBUG).to_string())
+}
+
+// Only http(s) URLs with a host make sense for a DNS caching test
+fn parse_url(s: &str) -> Result<Url, String> {
+    let url = Url::parse(s).map_err(|e| format!("malformed URL: {}", e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("unsupported scheme `{}`, expected http or https", url.scheme()));
+    }
+    if url.host_str().is_none() {
+        return Err("URL has no host to resolve".to_string());
     }
+    Ok(url)
+}
+
+// Record types are matched case-insensitively, e.g. `mx` or `MX`
+fn parse_record_type(s: &str) -> Result<RecordType, String> {
+    s.to_ascii_uppercase()
+        .parse()
+        .map_err(|_| format!("unknown record type `{}`", s))
+}
+
+// Intervals are a number with an `ms`, `s` or `m` suffix
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("expected a number followed by ms, s or m, got `{}`", s))?;
+    let interval = match unit {
+        "ms" => Duration::from_millis(value),
+        "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value * 60),
+        _ => return Err(format!("unknown unit `{}`, expected ms, s or m", unit)),
+    };
+    if interval.is_zero() {
+        return Err("interval must be greater than zero".to_string());
+    }
+    Ok(interval)
+}
+
+// Hostname patterns must have somewhere to put the number
+fn parse_host_template(s: &str) -> Result<String, String> {
+    if s.contains("{}") {
+        Ok(s.to_string())
+    } else {
+        Err(format!("`{}` has no `{{}}` for the host number", s))
+    }
+}
+
+// A name server address; a bare IP means the standard DNS port
+fn parse_dns_server(s: &str) -> Result<SocketAddr, String> {
+    if let Ok(ip) = s.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, 53));
+    }
+    s.parse()
+        .map_err(|_| "expected an IP address with an optional port, e.g. 127.0.0.1:5353 or [::1]:5353".to_string())
+}
+
+// A fraction of the backoff; 1 would allow retrying immediately
+fn parse_jitter(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(jitter) if (0.0..1.0).contains(&jitter) => Ok(jitter),
+        _ => Err(format!("`{}` isn't a fraction from 0 up to, but not including, 1", s)),
+    }
+}
+
+// Address ranges in CIDR notation, e.g. `10.0.0.0/8` or `fc00::/7`
+fn parse_cidr(s: &str) -> Result<IpNet, String> {
+    s.parse().map_err(|_| format!("`{}` isn't a CIDR range like 10.0.0.0/8", s))
+}
+
+// Status codes are three digits, from 100 to 999
+fn parse_status(s: &str) -> Result<StatusCode, String> {
+    StatusCode::from_bytes(s.trim().as_bytes()).map_err(|_| format!("invalid status code `{}`", s))
+}
+
+// Headers are given curl-style, e.g. `User-Agent: my-tool/1.0`
+fn parse_header(s: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| "expected `Name: value`".to_string())?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|e| format!("invalid header name: {}", e))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|e| format!("invalid header value: {}", e))?;
+    Ok((name, value))
 }
 
 // Annotate the main function with `instrument` for automatic tracing
 #[tokio::main]
 #[instrument]
-async fn main() -> Result<(), Box<dyn Error>> {
-    // Generate a time-based filename (e.g., app-2025-03-11T163000.log)
-    let timestamp = Utc::now().format("%Y-%m-%dT%H%M%S").to_string();
-    let filename = format!("app-{}.log", timestamp);
-
-    // Open or create the log file
-    let log_file = OpenOptions::new()
-        .write(true)
-        .append(true) // Append to file (though each run gets a new file due to timestamp)
-        .create(true) // Create the file if it doesn't exist
-        .open(&filename)?;
-
-    // Set up tracing to write to the log file
-    let file_layer = Layer::new()
-        .with_writer(log_file)
-        .with_ansi(false) // Disable colors in file output
+async fn main() -> Result<ExitCode, DnsCacheError> {
+    // Parse and validate arguments before touching the log file or network
+    let args = parse_args();
+
+    // Pick the log destination: stdout, a fixed file, or a new file per run.
+    // Logs are written on a background thread; holding the guard until the
+    // end of main makes sure everything buffered gets flushed on exit.
+    let ((writer, _log_guard), ansi, destination) = if args.log_stdout {
+        let ansi = std::io::stdout().is_terminal(); // Only colorize when a human is watching
+        (log_writer(std::io::stdout()), ansi, "stdout".to_string())
+    } else {
+        let filename = match &args.log_file {
+            Some(path) => path.display().to_string(),
+            None => {
+                // Generate a time-based filename (e.g., app-2025-03-11T163000.log)
+                let timestamp = Utc::now().format("%Y-%m-%dT%H%M%S").to_string();
+                format!("app-{}.log", timestamp)
+            }
+        };
+
+        // Open or create the log file
+        let log_file = OpenOptions::new()
+            .append(true) // Append to file (a fixed --log-file accumulates runs)
+            .create(true) // Create the file if it doesn't exist
+            .open(&filename);
+
+        match log_file {
+            Ok(log_file) => (log_writer(log_file), false, filename), // Disable colors in file output
+            // A read-only working directory (common in CI) shouldn't cost the run
+            Err(e) => {
+                eprintln!("Can't open log file {}: {}; logging to stdout instead", filename, e);
+                let ansi = std::io::stdout().is_terminal();
+                (log_writer(std::io::stdout()), ansi, "stdout".to_string())
+            }
+        }
+    };
+
+    // Set up tracing to write to the chosen destination
+    let log_layer = Layer::new()
+        .with_writer(writer)
+        .with_ansi(ansi)
         .with_target(true) // Include module/function targets in logs
         .with_line_number(true); // Include line numbers for debugging
+    let log_layer = match args.log_format {
+        LogFormat::Text => log_layer.boxed(),
+        LogFormat::Json => log_layer.json().boxed(),
+    };
 
-    tracing_subscriber::registry()
-        .with(file_layer)
-        .with(tracing_subscriber::filter::LevelFilter::DEBUG) // Set max level to DEBUG
-        .init();
+    // The filter only applies to the log, so `--summary-only` doesn't hide
+    // the resolver's spans from the exporter
+    let log_filter = log_filter(args.summary_only, args.log_level, args.log_level_from_flag);
+    let subscriber = tracing_subscriber::registry().with(log_layer.with_filter(log_filter));
+    // Exported alongside the log; dropping the guard flushes pending spans
+    #[cfg(feature = "otel")]
+    let (subscriber, _otel_guard) = {
+        let (layer, guard) = match &args.otlp_endpoint {
+            Some(endpoint) => {
+                let (layer, guard) = otel::layer(endpoint)?;
+                (Some(layer.with_filter(LevelFilter::INFO)), Some(guard))
+            }
+            None => (None, None),
+        };
+        (subscriber.with(layer), guard)
+    };
+    subscriber.init();
 
-    info!("Starting the application, logging to {}", filename);
+    info!("Starting the application, logging to {}", destination);
 
     // Create our custom DNS resolver
-    let dns_resolver = HickoryDnsResolver::new();
+    let mut resolver_builder = HickoryDnsResolver::builder()
+        .timeout(Duration::from_millis(args.dns_timeout))
+        .timing_breakdown(args.dns_timing_breakdown)
+        .deny_private(args.deny_private)
+        .deny_cidrs(args.deny_cidr.clone());
+    if let Some(size) = args.dns_cache_size {
+        resolver_builder = resolver_builder.cache_size(size);
+    }
+    if let Some(upstream) = args.upstream {
+        resolver_builder = resolver_builder.upstream(upstream.into());
+    }
+    if let Some(addr) = args.dns_server {
+        resolver_builder = resolver_builder.name_servers(vec![addr]);
+        if args.dns_protocol == DnsTransport::Tcp {
+            resolver_builder = resolver_builder.protocol(DnsProtocol::Tcp);
+        }
+    }
+    // Behind a proxy its host is the only one we resolve, so say where it went
+    if let Some(proxy_host) = args.proxy.as_ref().and_then(Url::host_str).map(str::to_string) {
+        resolver_builder = resolver_builder.on_resolve(move |host, ips| {
+            if host == proxy_host {
+                info!(proxy = host, addrs = ?ips, "Resolved proxy host");
+            }
+        });
+    }
+    let dns_resolver = resolver_builder.build()?;
+    dns_resolver.log_config();
+    // Declared after the log guard, so it's dropped first and its summary
+    // still gets flushed, on every way out of main including Ctrl-C
+    let _summary_guard = SummaryGuard(dns_resolver.clone());
+    if let Some(path) = &args.cache_file {
+        match dns_resolver.load_cache(path) {
+            Ok(count) => info!("Loaded {} cached DNS answers from {}", count, path.display()),
+            // First run, nothing saved yet
+            Err(DnsCacheError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Ignoring DNS cache file {}: {}", path.display(), e),
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = &args.cache_server {
+        tokio::select! {
+            result = cache_server::serve(dns_resolver.clone(), path) => result?,
+            _ = tokio::signal::ctrl_c() => info!("Interrupted, stopping the cache server"),
+        }
+        // Reached only if we were the ones listening on it
+        let _ = std::fs::remove_file(path);
+        save_cache(&dns_resolver, args.cache_file.as_deref());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    match &args.command {
+        Some(Command::Resolve { host, repeat, no_cache, ptr }) => {
+            resolve_only(&dns_resolver, host, *repeat, *no_cache, *ptr).await?;
+            save_cache(&dns_resolver, args.cache_file.as_deref());
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::WarmupCurve { host, count }) => {
+            warmup_curve(&dns_resolver, host, *count).await?;
+            save_cache(&dns_resolver, args.cache_file.as_deref());
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Probe { host, interval, window, count }) => {
+            probe(&dns_resolver, host, *interval, *window, *count).await;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::CachePressure { extra, template }) => {
+            let evicted = cache_pressure(&dns_resolver, *extra, template).await?;
+            return Ok(if evicted { ExitCode::SUCCESS } else { ExitCode::FAILURE });
+        }
+        Some(Command::Srv { name }) => {
+            print_srv(&dns_resolver, name, args.format).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Txt { name }) => {
+            print_txt(&dns_resolver, name, args.format).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Query { name, record_type }) => {
+            print_records(&dns_resolver, name, *record_type, args.format).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Cname { host }) => {
+            print_cname(&dns_resolver, host, args.format).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => {}
+    }
+    if args.dry_run {
+        dry_run(&dns_resolver, &args.urls).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.emit_hosts {
+        let hosts = match &args.hosts_file {
+            Some(path) => read_hostnames(path)?,
+            None => url_hosts(&args.urls).into_iter().map(str::to_string).collect(),
+        };
+        let resolved = emit_hosts(&dns_resolver, &hosts, args.all_addresses).await;
+        save_cache(&dns_resolver, args.cache_file.as_deref());
+        return Ok(if resolved { ExitCode::SUCCESS } else { ExitCode::FAILURE });
+    }
     
+    // reqwest doesn't resolve address literals, so they're screened here
+    for url in &args.urls {
+        dns_resolver.check_url(url)?;
+    }
+
     // Build the reqwest client with our custom resolver
-    let client = Client::builder()
-        .dns_resolver(Arc::new(dns_resolver))
-        .timeout(Duration::from_secs(10)) // Overall request timeout
-        .build()?;
+    let builder = client_builder(&args)?.redirect(dns_resolver.redirect_policy());
+    let (builder, probe) = request_resolver(&args, builder, &dns_resolver);
+    let client = builder.build()?;
 
     debug!("Client built successfully with custom DNS resolver");
 
-    // Define the URL to test DNS caching
-    let url = "https://google.com";
+    #[cfg_attr(not(feature = "compression"), allow(unused_mut))]
+    let mut headers: HeaderMap = args.headers.iter().cloned().collect();
+    // reqwest's own decompression drops Content-Length, so we ask for
+    // compression ourselves and decompress in `fetch_url` to see both sizes
+    #[cfg(feature = "compression")]
+    if args.compression {
+        headers
+            .entry(reqwest::header::ACCEPT_ENCODING)
+            .or_insert(HeaderValue::from_static("gzip, br"));
+    }
+
+    let options = Arc::new(FetchOptions {
+        headers,
+        retry: RetryPolicy {
+            max_retries: args.max_retries,
+            base_backoff: Duration::from_millis(args.retry_backoff_ms),
+            jitter: args.retry_jitter,
+        },
+        read_body: args.read_body,
+        max_body_bytes: args.max_body_bytes,
+        quiet: args.summary_only,
+        dns_slo: args.dns_slo,
+        dns_host: args
+            .proxy
+            .as_ref()
+            .and_then(|proxy| proxy.host_str())
+            .map(str::to_string),
+    });
+
+    if let Some(path) = &args.hosts_file {
+        let hosts = read_hostnames(path)?;
+        let template = args.fetch.then(|| &args.urls[0]);
+        replay_hosts(&client, &dns_resolver, hosts, template, &options, &args).await;
+        save_cache(&dns_resolver, args.cache_file.as_deref());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.compare {
+        let system = SystemResolver;
+        let system_client = client_builder(&args)?
+            .redirect(dns_resolver.redirect_policy())
+            .dns_resolver(Arc::new(system.clone()))
+            .build()?;
+
+        info!("Comparison run 1 of 2: caching resolver");
+        let caching_run = run_requests(&args, &client, Arc::new(dns_resolver.clone()), &options).await?;
+        if !caching_run.interrupted {
+            info!("Comparison run 2 of 2: system resolver");
+            let system_run = run_requests(&args, &system_client, Arc::new(system), &options).await?;
+            print_comparison(&caching_run, &system_run, args.format);
+        }
+        save_cache(&dns_resolver, args.cache_file.as_deref());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let run = run_requests(&args, &client, probe, &options).await?;
+    let Run { warmup, timings, failures, interrupted } = &run;
+
+    let stats = dns_resolver.stats();
+    if !*interrupted {
+        info!("All requests completed");
+    }
+    info!("{} requests succeeded, {} failed", timings.len(), failures);
+    if args.format == OutputFormat::Text {
+        println!("Succeeded: {}, failed: {}", timings.len(), failures);
+    }
+    info!("DNS cache hits: {}, misses: {}", stats.hits, stats.misses);
+    for entry in dns_resolver.cached_entries() {
+        debug!("Cached {} -> {:?}, expires in {:?}", entry.host, entry.addrs, entry.ttl_remaining());
+    }
+    save_cache(&dns_resolver, args.cache_file.as_deref());
+    // The resolver's summary would include the warm-up's cold lookups
+    let latency = if warmup.is_empty() {
+        dns_resolver.latency_summary()
+    } else {
+        let lookups: Vec<Duration> = timings
+            .iter()
+            .filter(|t| !t.connection_reused)
+            .map(|t| t.dns_time)
+            .collect();
+        LatencySummary::from_durations(&lookups)
+    };
+    let unexpected = timings
+        .iter()
+        .filter(|t| !status_expected(t.status, &args.expect_status))
+        .count();
+    let slo = args.dns_slo.map(|budget| SloReport::new(timings, budget));
+    if let Some(slo) = slo {
+        info!(
+            violations = slo.violations,
+            lookups = slo.lookups,
+            slo_ms = slo.budget.as_secs_f64() * 1000.0,
+            "DNS SLO violation rate {:.1}%",
+            slo.rate() * 100.0
+        );
+    }
+    match args.format {
+        OutputFormat::Text => {
+            print_warmup(warmup);
+            print_summary(timings, latency);
+            if !args.expect_status.is_empty() {
+                println!("Unexpected status: {} of {} requests", unexpected, timings.len());
+            }
+            if let Some(slo) = slo {
+                println!(
+                    "DNS SLO ({:?}): {} of {} lookups over budget ({:.1}%)",
+                    slo.budget,
+                    slo.violations,
+                    slo.lookups,
+                    slo.rate() * 100.0
+                );
+            }
+        }
+        OutputFormat::Json => print_json(&run, unexpected, slo, stats, latency),
+    }
+
+    // A CI check fails on any unexpected status
+    if unexpected > 0 {
+        warn!("{} requests returned an unexpected status", unexpected);
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+// Whether `status` is one of `expected`; with none given, any status is
+fn status_expected(status: StatusCode, expected: &[StatusCode]) -> bool {
+    expected.is_empty() || expected.contains(&status)
+}
+
+/// Logs the resolver's lifetime stats when dropped at the end of main.
+struct SummaryGuard(HickoryDnsResolver);
+
+impl Drop for SummaryGuard {
+    fn drop(&mut self) {
+        self.0.log_summary();
+    }
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use dns_query_caching::DnsCacheError;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+    use opentelemetry_sdk::Resource;
+    use reqwest::Url;
+    use tracing::Subscriber;
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// Shuts the span exporter down when dropped, sending any spans still
+    /// queued for the next batch.
+    pub struct Guard(SdkTracerProvider);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            if let Err(e) = self.0.shutdown() {
+                eprintln!("Failed to flush spans to the OTLP collector: {}", e);
+            }
+        }
+    }
+
+    // A layer exporting spans in batches to the OTLP/HTTP collector at
+    // `endpoint`, plus the guard that flushes them
+    pub fn layer<S>(endpoint: &Url) -> Result<(OpenTelemetryLayer<S, SdkTracer>, Guard), DnsCacheError>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let mut endpoint = endpoint.clone();
+        if endpoint.path() == "/" {
+            endpoint.set_path("/v1/traces");
+        }
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint.as_str())
+            .build()
+            .map_err(|e| DnsCacheError::InvalidConfig(format!("OTLP exporter: {}", e)))?;
+
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(env!("CARGO_BIN_NAME")).build())
+            .build();
+        let tracer = provider.tracer(env!("CARGO_BIN_NAME"));
+        Ok((tracing_opentelemetry::layer().with_tracer(tracer), Guard(provider)))
+    }
+}
+
+/// What the request loop needs from the resolver behind its client.
+trait ResolverProbe: Send + Sync {
+    /// The shortest remaining TTL of the cached answers for `hosts`, if the
+    /// resolver caches any.
+    fn min_ttl_remaining(&self, _hosts: &[&str]) -> Option<Duration> {
+        None
+    }
+}
+
+impl ResolverProbe for HickoryDnsResolver {
+    fn min_ttl_remaining(&self, hosts: &[&str]) -> Option<Duration> {
+        self.cached_entries()
+            .iter()
+            .filter(|entry| hosts.contains(&entry.host.as_str()))
+            .map(|entry| entry.ttl_remaining())
+            .min()
+    }
+}
+
+#[cfg(unix)]
+impl ResolverProbe for CacheServerClient {}
+
+// Give `builder` the resolver requests should use, which is the cache server
+// with `--use-cache-server` and our own resolver otherwise, along with the
+// probe that reads its cached TTLs
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn request_resolver(
+    args: &Args,
+    builder: ClientBuilder,
+    resolver: &HickoryDnsResolver,
+) -> (ClientBuilder, Arc<dyn ResolverProbe>) {
+    #[cfg(unix)]
+    if let Some(path) = &args.use_cache_server {
+        info!("Resolving through the cache server at {}", path.display());
+        // Half the request timeout, leaving the other half to resolve locally
+        let timeout = Duration::from_millis(args.request_timeout) / 2;
+        let shared = CacheServerClient::with_timeout(path.clone(), resolver.clone(), timeout);
+        return (builder.dns_resolver(Arc::new(shared.clone())), Arc::new(shared));
+    }
+    (builder.dns_resolver(Arc::new(resolver.clone())), Arc::new(resolver.clone()))
+}
+
+/// The baseline for `--compare`: the system resolver (`getaddrinfo`) with no
+/// cache of our own, which is what reqwest uses without its `hickory-dns`
+/// feature. Lookups are timed like `HickoryDnsResolver` times them.
+#[derive(Clone)]
+struct SystemResolver;
+
+impl ResolverProbe for SystemResolver {}
+
+impl reqwest::dns::Resolve for SystemResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let start = Instant::now();
+            let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            let elapsed = start.elapsed();
+            debug!("System resolver looked up {} in {:?}", host, elapsed);
+            record_lookup_time(&host, elapsed);
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+// The wait before the next round under `--pace ttl-aware`, from the
+// soonest-expiring cached answer for `hosts`: half its remaining TTL, so
+// rounds land ever closer to expiry, until it's within `TTL_PACE_MARGIN`;
+// then just past expiry, to measure the switch from warm to cold
+fn ttl_aware_pause(resolver: &dyn ResolverProbe, hosts: &[&str]) -> Option<Duration> {
+    let remaining = resolver.min_ttl_remaining(hosts)?;
+
+    let pause = if remaining > TTL_PACE_MARGIN {
+        remaining / 2
+    } else {
+        remaining + TTL_PACE_MARGIN
+    };
+    debug!("Cached answer expires in {:?}, waiting {:?}", remaining, pause);
+    Some(pause)
+}
+
+// The reqwest client configuration from the command line, minus the
+// resolver, so `--compare` can build two otherwise identical clients
+fn client_builder(args: &Args) -> Result<ClientBuilder, DnsCacheError> {
+    let mut builder = Client::builder()
+        .timeout(Duration::from_millis(args.request_timeout)) // Overall request timeout
+        .user_agent(&args.user_agent);
+
+    // Connection reuse decides whether a request consults the resolver at all
+    if let Some(ms) = args.pool_idle_timeout {
+        builder = builder.pool_idle_timeout(Duration::from_millis(ms));
+    }
+    if let Some(max) = args.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    if let Some(nodelay) = args.tcp_nodelay {
+        builder = builder.tcp_nodelay(nodelay);
+    }
+    if let Some(keepalive) = args.tcp_keepalive {
+        builder = builder.tcp_keepalive(keepalive);
+    }
+    builder = match args.http_version {
+        HttpVersion::Auto => builder,
+        HttpVersion::Http1 => builder.http1_only(),
+        HttpVersion::Http2 => builder.http2_prior_knowledge(),
+    };
+    // Behind a proxy, reqwest only resolves the proxy's host; the target
+    // host is sent to the proxy as-is and resolved there
+    if let Some(proxy) = &args.proxy {
+        builder = builder.proxy(Proxy::all(proxy.clone())?);
+    }
+    if let Some(path) = &args.ca_cert {
+        let pem = std::fs::read(path)?;
+        builder = builder.add_root_certificate(Certificate::from_pem(&pem)?);
+        info!("Trusting the root certificate in {}", path.display());
+    }
+    if args.insecure {
+        // On stderr too, since the log may be going to a file nobody reads
+        warn!("TLS certificate verification is disabled (--insecure)");
+        eprintln!("WARNING: TLS certificate verification is disabled; connections are not secure");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder)
+}
+
+/// Outcome of one run of the request loop.
+struct Run {
+    /// The `--warmup` requests, left out of `timings`.
+    warmup: Vec<RequestTiming>,
+    timings: Vec<RequestTiming>,
+    failures: u32,
+    /// Whether Ctrl-C cut the run short.
+    interrupted: bool,
+}
+
+// Make multiple requests to demonstrate caching and measure DNS time,
+// `--concurrency` at a time, through `client`, whose resolver `resolver`
+// reports on
+async fn run_requests(
+    args: &Args,
+    client: &Client,
+    resolver: Arc<dyn ResolverProbe>,
+    options: &Arc<FetchOptions>,
+) -> Result<Run, DnsCacheError> {
+    // Stop early on Ctrl-C, still reporting what was measured so far
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+    let mut interrupted = false;
+
+    // In watch mode, rounds run on a timer until Ctrl-C
+    let pause = match args.interval {
+        Some(interval) => interval,
+        None if args.watch => Duration::from_secs(5),
+        None => Duration::from_millis(args.delay_ms),
+    };
+    // The hosts whose cached answers `--pace ttl-aware` follows
+    let dns_hosts: Vec<&str> = match &options.dns_host {
+        Some(host) => vec![host.as_str()],
+        None => args.urls.iter().filter_map(Url::host_str).collect(),
+    };
+
+    // Sequential, so the first one alone pays for the cold lookup and the
+    // connection, and cycling through the URLs like the measured requests
+    let mut warmup = Vec::with_capacity(args.warmup as usize);
+    for i in 1..=args.warmup {
+        let url = &args.urls[(i as usize - 1) % args.urls.len()];
+        per_request!(options.quiet, "Starting warm-up request #{}", i);
+        let result = tokio::select! {
+            result = fetch_url(client, url, options) => result,
+            _ = &mut shutdown => {
+                warn!("Interrupted during warm-up request #{}", i);
+                interrupted = true;
+                break;
+            }
+        };
+        match result {
+            Ok(timing) => {
+                per_request!(
+                    options.quiet,
+                    "Warm-up request #{} completed with status: {} in {:?} (DNS: {:?})",
+                    i, timing.status, timing.total_time, timing.dns_time
+                );
+                warmup.push(timing);
+            }
+            Err(e) if args.fail_fast => return Err(e),
+            Err(e) => warn!("Warm-up request failed: {}", e),
+        }
+    }
+
+    let mut timings = Vec::with_capacity(args.count as usize);
+    let mut failures = 0;
+    let mut next = 1;
+    while !interrupted && (args.watch || next <= args.count) {
+        let last = if args.watch { u32::MAX } else { args.count };
+        let batch: Vec<u32> = (next..=last).take(args.concurrency as usize).collect();
+        next += batch.len() as u32;
+        // No point waiting after the last round
+        let more_rounds = args.watch || next <= args.count;
+
+        let mut tasks: Vec<_> = batch
+            .into_iter()
+            .map(|i| {
+                let client = client.clone();
+                // Round-robin over the targets, so duplicates get more turns
+                let url = args.urls[(i as usize - 1) % args.urls.len()].clone();
+                let options = options.clone();
+                tokio::spawn(
+                    async move {
+                        per_request!(options.quiet, "Starting request #{}", i);
+                        let timing = fetch_url(&client, &url, &options).await?;
+                        per_request!(
+                            options.quiet,
+                            "Request #{} completed with status: {} in {:?} (DNS: {:?})",
+                            i, timing.status, timing.total_time, timing.dns_time
+                        );
+                        Ok::<_, DnsCacheError>(timing)
+                    }
+                    .in_current_span(),
+                )
+            })
+            .collect();
+
+        let results = tokio::select! {
+            results = join_all(tasks.iter_mut()) => results,
+            _ = &mut shutdown => {
+                warn!("Interrupted, stopping after {} requests", timings.len());
+                // Cancel the requests still in flight and wait for them to
+                // wind down, so none of them logs after the summary
+                for task in &tasks {
+                    task.abort();
+                }
+                join_all(tasks).await;
+                interrupted = true;
+                break;
+            }
+        };
+        for result in results {
+            match result.expect("request task panicked") {
+                Ok(timing) => {
+                    if !status_expected(timing.status, &args.expect_status) {
+                        warn!(
+                            "{} returned {}, expected one of {:?}",
+                            timing.host, timing.status, args.expect_status
+                        );
+                    }
+                    timings.push(timing);
+                }
+                Err(e) if args.fail_fast => return Err(e),
+                // One transient failure shouldn't end a long run
+                Err(e) => {
+                    warn!("Request failed: {}", e);
+                    failures += 1;
+                }
+            }
+        }
+
+        if more_rounds {
+            let pause = match args.pace {
+                Pace::Fixed => pause,
+                Pace::TtlAware => ttl_aware_pause(&*resolver, &dns_hosts).unwrap_or(pause),
+            };
+            // Zero means back-to-back, without even yielding to the timer
+            if !pause.is_zero() {
+                tokio::select! {
+                    _ = tokio::time::sleep(pause) => {}
+                    _ = &mut shutdown => {
+                        warn!("Interrupted, stopping after {} requests", timings.len());
+                        interrupted = true;
+                        break;
+                    }
+                }
+            }
+        }
+    }
 
-    // Make multiple requests to demonstrate caching and measure DNS time
-    for i in 1..=5 {  
-        info!("Starting request #{}", i);
+    Ok(Run {
+        warmup,
+        timings,
+        failures,
+        interrupted,
+    })
+}
+
+// Write the cache back for the next run, if `--cache-file` was given. A
+// failure here only costs the next run a cold start.
+fn save_cache(resolver: &HickoryDnsResolver, path: Option<&Path>) {
+    let Some(path) = path else {
+        return;
+    };
+    match resolver.save_cache(path) {
+        Ok(()) => debug!("Saved DNS cache to {}", path.display()),
+        Err(e) => warn!("Failed to save DNS cache to {}: {}", path.display(), e),
+    }
+}
+
+// Resolve `host` `repeat` times, printing the addresses and how long each
+// lookup took. Only the first lookup should need the network, unless
+// `no_cache` makes every one of them go upstream.
+#[instrument(skip(resolver))]
+async fn resolve_only(
+    resolver: &HickoryDnsResolver,
+    host: &str,
+    repeat: u32,
+    no_cache: bool,
+    ptr: bool,
+) -> Result<(), DnsCacheError> {
+    let mut addrs = Vec::new();
+    for attempt in 1..=repeat {
+        // A fresh lookup doesn't report TTLs
+        let start = Instant::now();
+        let entries: Vec<(IpAddr, Option<Duration>)> = if no_cache {
+            let ips = resolver.resolve_host_fresh(host).await?;
+            ips.into_iter().map(|ip| (ip, None)).collect()
+        } else {
+            let entries = resolver.resolve_host_with_ttl(host).await?;
+            entries.into_iter().map(|(ip, ttl)| (ip, Some(ttl))).collect()
+        };
+        let elapsed = start.elapsed();
+
+        println!("Lookup #{}: {:?}", attempt, elapsed);
+        let rows: Vec<[String; 3]> = entries
+            .iter()
+            .map(|(ip, ttl)| {
+                let record_type = if ip.is_ipv4() { RecordType::A } else { RecordType::AAAA };
+                let ttl = ttl.map_or("-".to_string(), |ttl| format!("{}s", ttl.as_secs()));
+                [ip.to_string(), record_type.to_string(), ttl]
+            })
+            .collect();
+        print_aligned(&rows, "  ");
+        addrs = entries.into_iter().map(|(ip, _)| ip).collect();
+    }
+
+    // After the timed lookups, so PTR queries don't skew them
+    if ptr {
+        for ip in addrs {
+            let names = resolver.reverse_lookup(ip).await?;
+            if names.is_empty() {
+                println!("{} has no PTR record", ip);
+            } else {
+                println!("{} -> {}", ip, names.join(", "));
+            }
+        }
+    }
+
+    let stats = resolver.stats();
+    info!("DNS cache hits: {}, misses: {}", stats.hits, stats.misses);
+    println!("Cache hits: {}, misses: {}", stats.hits, stats.misses);
+    Ok(())
+}
+
+// Resolve `host` `count` times in immediate succession, printing one CSV row
+// per lookup: its number, duration and whether the cache answered it
+async fn warmup_curve(resolver: &HickoryDnsResolver, host: &str, count: u32) -> Result<(), DnsCacheError> {
+    println!("lookup,time_ms,cached");
+    for lookup in 1..=count {
+        let hits = resolver.stats().hits;
         let start = Instant::now();
-        let response = fetch_url(&client, url).await?;
-        let total_time = start.elapsed();
-        info!("Request #{} completed with status: {} in {:?}", i, response.status(), total_time);
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        resolver.resolve_host(host).await?;
+        let elapsed = start.elapsed();
+        let cached = resolver.stats().hits > hits;
+
+        debug!("Lookup #{} of {} took {:?}, cached: {}", lookup, host, elapsed, cached);
+        println!("{},{:.3},{}", lookup, elapsed.as_secs_f64() * 1000.0, cached);
     }
+    Ok(())
+}
+
+// Resolve `host` upstream every `interval`, bypassing the cache so each lookup
+// measures the wire, and log the p50/p99 over the latest `window` lookups.
+// Runs until Ctrl-C or `count` lookups; failures are logged and skipped.
+async fn probe(resolver: &HickoryDnsResolver, host: &str, interval: Duration, window: u32, count: Option<u32>) {
+    let mut recent = VecDeque::with_capacity(window as usize);
+    let mut failures = 0;
+    let mut ticks = tokio::time::interval(interval);
+    // A lookup slower than the interval delays the next one, rather than
+    // being followed by a burst
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
 
-    info!("All requests completed");
+    let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+    let mut lookup = 0;
+    while count.is_none_or(|count| lookup < count) {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = &mut shutdown => {
+                info!("Interrupted, stopping after {} probes", lookup);
+                break;
+            }
+        }
+        lookup += 1;
+
+        let start = Instant::now();
+        let result = resolver.resolve_host_fresh(host).await;
+        let elapsed = start.elapsed();
+        if let Err(e) = result {
+            warn!("Probe #{} of {} failed after {:?}: {}", lookup, host, elapsed, e);
+            println!("Probe #{}: failed ({})", lookup, e);
+            failures += 1;
+            continue;
+        }
+
+        if recent.len() == window as usize {
+            recent.pop_front();
+        }
+        recent.push_back(elapsed);
+        let latency = LatencySummary::from_durations(recent.make_contiguous()).expect("just pushed a lookup");
+        info!(
+            host,
+            duration_ms = millis(elapsed),
+            p50_ms = millis(latency.p50),
+            p99_ms = millis(latency.p99),
+            samples = latency.count,
+            "DNS probe #{}",
+            lookup
+        );
+        println!(
+            "Probe #{}: {:?} (p50 {:?}, p99 {:?} over the last {})",
+            lookup, elapsed, latency.p50, latency.p99, latency.count
+        );
+    }
+
+    if failures > 0 {
+        println!("{} of {} probes failed", failures, lookup);
+    }
+}
+
+// Resolve `extra` more hosts generated from `template` than the cache holds,
+// then re-resolve the first `extra` of them, which the rest should have pushed
+// out. Returns whether every one of those had to be fetched again.
+async fn cache_pressure(resolver: &HickoryDnsResolver, extra: u32, template: &str) -> Result<bool, DnsCacheError> {
+    let capacity = resolver.cache_size();
+    let hosts: Vec<String> = (1..=capacity + extra as usize)
+        .map(|i| template.replace("{}", &i.to_string()))
+        .collect();
+    let (early, filler) = hosts.split_at(extra as usize);
+
+    // One at a time, so the early hosts are certainly the oldest entries; the
+    // filler can then go in parallel
+    let start = Instant::now();
+    for host in early {
+        resolver.resolve_host(host).await?;
+    }
+    let filler: Vec<&str> = filler.iter().map(String::as_str).collect();
+    let failed = resolver.prefetch(&filler).await.values().filter(|r| r.is_err()).count();
+    if failed > 0 {
+        warn!("{} of {} filler hosts failed to resolve, so may not take up cache space", failed, filler.len());
+    }
+    info!("Resolved {} distinct hosts with a cache size of {} in {:?}", hosts.len(), capacity, start.elapsed());
+    println!("Resolved {} distinct hosts with a cache size of {}", hosts.len(), capacity);
+
+    // A miss means upstream was asked again, so the entry had been evicted
+    let mut refetched = 0;
+    for host in early {
+        let misses = resolver.stats().misses;
+        resolver.resolve_host(host).await?;
+        let evicted = resolver.stats().misses > misses;
+        debug!("Re-resolved {}, evicted: {}", host, evicted);
+        println!("{} {}", host, if evicted { "was evicted" } else { "was still cached" });
+        refetched += usize::from(evicted);
+    }
+
+    info!("{} of the first {} hosts were evicted", refetched, early.len());
+    println!("Evicted: {} of the first {} hosts", refetched, early.len());
+    Ok(refetched == early.len())
+}
+
+// Resolve the host of each of `urls` once, printing the outcome, without
+// touching HTTP. Returns the first lookup error, if any, so the exit code
+// tells whether DNS is healthy.
+async fn dry_run(resolver: &HickoryDnsResolver, urls: &[Url]) -> Result<(), DnsCacheError> {
+    let hosts = url_hosts(urls);
+    let mut first_error = None;
+    let mut failed = 0;
+    for host in &hosts {
+        let start = Instant::now();
+        match resolver.resolve_host(host).await {
+            Ok(ips) => {
+                let ips: Vec<String> = ips.iter().map(ToString::to_string).collect();
+                println!("ok   {} -> {} ({:?})", host, ips.join(", "), start.elapsed());
+            }
+            Err(e) => {
+                println!("FAIL {}: {}", host, e);
+                failed += 1;
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    info!("Dry run: {} of {} hosts resolved", hosts.len() - failed, hosts.len());
+    println!("Resolved {} of {} hosts", hosts.len() - failed, hosts.len());
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// Resolve each of `hosts` and print it in hosts-file format, one line per
+// address: only the first address unless `all_addresses`. Failures go to
+// stderr so stdout stays a valid hosts file. Returns whether every host
+// resolved.
+async fn emit_hosts(resolver: &HickoryDnsResolver, hosts: &[String], all_addresses: bool) -> bool {
+    let mut failed = 0;
+    for host in hosts {
+        match resolver.resolve_host(host).await {
+            Ok(ips) => {
+                let count = if all_addresses { ips.len() } else { 1 };
+                for ip in ips.iter().take(count) {
+                    println!("{} {}", ip, host);
+                }
+            }
+            Err(e) => {
+                warn!("Not emitting {}: {}", host, e);
+                eprintln!("Can't resolve {}: {}", host, e);
+                failed += 1;
+            }
+        }
+    }
+    info!("Emitted hosts entries for {} of {} hosts", hosts.len() - failed, hosts.len());
+    failed == 0
+}
+
+// The distinct hosts of `urls`, in the order they first appear
+fn url_hosts(urls: &[Url]) -> Vec<&str> {
+    let mut hosts: Vec<&str> = Vec::new();
+    for host in urls.iter().filter_map(Url::host_str) {
+        if !hosts.contains(&host) {
+            hosts.push(host);
+        }
+    }
+    hosts
+}
+
+// Read the hostnames to replay from `path`, one per line. Lines starting
+// with `#` are comments; blank and malformed lines are skipped with a warning.
+fn read_hostnames(path: &Path) -> io::Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut hosts = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        if line.is_empty() {
+            warn!("{}:{}: skipping empty line", path.display(), number + 1);
+            continue;
+        }
+        if !is_hostname(line) {
+            warn!("{}:{}: skipping malformed hostname `{}`", path.display(), number + 1, line);
+            continue;
+        }
+        hosts.push(line.to_string());
+    }
+    info!("Read {} hostnames from {}", hosts.len(), path.display());
+    Ok(hosts)
+}
+
+// Dot-separated labels of letters, digits, `-` and `_`, as DNS names are
+// written in practice
+fn is_hostname(s: &str) -> bool {
+    let s = s.strip_suffix('.').unwrap_or(s);
+    !s.is_empty()
+        && s.len() <= 253
+        && s.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+// Resolve each of `hosts`, `--concurrency` at a time, and also fetch
+// `template` with its host replaced, if given. Reports success and failure
+// counts and lookup time percentiles.
+async fn replay_hosts(
+    client: &Client,
+    resolver: &HickoryDnsResolver,
+    hosts: Vec<String>,
+    template: Option<&Url>,
+    options: &FetchOptions,
+    args: &Args,
+) {
+    let total = hosts.len();
+    let results: Vec<_> = futures::stream::iter(hosts)
+        .map(|host| async move {
+            let start = Instant::now();
+            let resolved = resolver.resolve_host(&host).await;
+            let lookup_time = start.elapsed();
+            match &resolved {
+                Ok(ips) => per_request!(options.quiet, "Resolved {} to {} addresses in {:?}", host, ips.len(), lookup_time),
+                Err(e) => warn!("Failed to resolve {}: {}", host, e),
+            }
+
+            let fetched = match (template, &resolved) {
+                (Some(template), Ok(_)) => {
+                    let mut url = template.clone();
+                    url.set_host(Some(&host)).expect("hostnames are valid URL hosts");
+                    let fetched = match resolver.check_url(&url) {
+                        Ok(()) => fetch_url(client, &url, options).await,
+                        Err(e) => Err(e),
+                    };
+                    match fetched {
+                        Ok(timing) => {
+                            per_request!(options.quiet, "Fetched {} with status {} in {:?}", url, timing.status, timing.total_time);
+                            Some(true)
+                        }
+                        Err(e) => {
+                            warn!("Failed to fetch {}: {}", url, e);
+                            Some(false)
+                        }
+                    }
+                }
+                _ => None,
+            };
+            (resolved.is_ok().then_some(lookup_time), fetched)
+        })
+        .buffer_unordered(args.concurrency as usize)
+        .collect()
+        .await;
+
+    let lookup_times: Vec<Duration> = results.iter().filter_map(|(time, _)| *time).collect();
+    let resolved = lookup_times.len();
+    let fetched = results.iter().filter(|(_, fetched)| *fetched == Some(true)).count();
+    let fetch_failed = results.iter().filter(|(_, fetched)| *fetched == Some(false)).count();
+    let latency = LatencySummary::from_durations(&lookup_times);
+    info!("Resolved {} of {} hosts, {} failed", resolved, total, total - resolved);
+
+    match args.format {
+        OutputFormat::Text => {
+            println!("Hosts: {}, resolved: {}, failed: {}", total, resolved, total - resolved);
+            if template.is_some() {
+                println!("Fetched: {}, failed: {}", fetched, fetch_failed);
+            }
+            if let Some(l) = latency {
+                println!("Lookup time: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}", l.p50, l.p90, l.p99, l.max);
+            }
+        }
+        OutputFormat::Json => {
+            let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+            let output = json!({
+                "hosts": total,
+                "resolved": resolved,
+                "failed": total - resolved,
+                "fetched": template.map(|_| fetched),
+                "fetch_failed": template.map(|_| fetch_failed),
+                "lookup_time": latency.map(|l| json!({
+                    "p50_ms": millis(l.p50),
+                    "p90_ms": millis(l.p90),
+                    "p99_ms": millis(l.p99),
+                    "max_ms": millis(l.max),
+                })),
+            });
+            println!("{}", output);
+        }
+    }
+}
+
+// Print `rows` of value, record type and TTL, dig-style, with each column
+// padded to its widest entry
+fn print_aligned(rows: &[[String; 3]], indent: &str) {
+    let width = |column: usize| rows.iter().map(|row| row[column].len()).max().unwrap_or(0);
+    let (value_width, type_width) = (width(0), width(1));
+    for [value, record_type, ttl] in rows {
+        println!("{}{:<value_width$}  {:<type_width$}  {}", indent, value, record_type, ttl);
+    }
+}
+
+// Print the `record_type` records of `name` as aligned data, type and TTL
+// columns, or as a JSON array with each record's name, type, TTL and data
+async fn print_records(
+    resolver: &HickoryDnsResolver,
+    name: &str,
+    record_type: RecordType,
+    format: OutputFormat,
+) -> Result<(), DnsCacheError> {
+    let records = resolver.lookup(name, record_type).await?;
+    match format {
+        OutputFormat::Text => {
+            let rows: Vec<[String; 3]> = records
+                .iter()
+                .map(|record| {
+                    let data = record.data().map_or(String::new(), ToString::to_string);
+                    [data, record.record_type().to_string(), format!("{}s", record.ttl())]
+                })
+                .collect();
+            print_aligned(&rows, "");
+        }
+        OutputFormat::Json => {
+            let records: Vec<_> = records
+                .iter()
+                .map(|record| {
+                    json!({
+                        "name": record.name().to_string(),
+                        "type": record.record_type().to_string(),
+                        "ttl": record.ttl(),
+                        "data": record.data().map(ToString::to_string),
+                    })
+                })
+                .collect();
+            println!("{}", json!(records));
+        }
+    }
     Ok(())
 }
 
+// Print the SRV records of `name`, one per line or as a JSON array
+async fn print_srv(resolver: &HickoryDnsResolver, name: &str, format: OutputFormat) -> Result<(), DnsCacheError> {
+    let records = resolver.resolve_srv(name).await?;
+    match format {
+        OutputFormat::Text => {
+            for srv in &records {
+                println!(
+                    "{}:{} (priority {}, weight {})",
+                    srv.target, srv.port, srv.priority, srv.weight
+                );
+            }
+        }
+        OutputFormat::Json => println!("{}", json!(records)),
+    }
+    Ok(())
+}
+
+// Print the TXT records of `name`, one per line or as a JSON array
+async fn print_txt(resolver: &HickoryDnsResolver, name: &str, format: OutputFormat) -> Result<(), DnsCacheError> {
+    let records = resolver.resolve_txt(name).await?;
+    match format {
+        OutputFormat::Text => records.iter().for_each(|txt| println!("{}", txt)),
+        OutputFormat::Json => println!("{}", json!(records)),
+    }
+    Ok(())
+}
+
+// Print the CNAME chain of `host` as `a -> b -> c`, or as a JSON array
+async fn print_cname(resolver: &HickoryDnsResolver, host: &str, format: OutputFormat) -> Result<(), DnsCacheError> {
+    let chain = resolver.trace_cname(host).await?;
+    match format {
+        OutputFormat::Text => println!("{}", chain.join(" -> ")),
+        OutputFormat::Json => println!("{}", json!(chain)),
+    }
+    Ok(())
+}
+
+/// Timing breakdown of a single request made by `fetch_url`.
+#[derive(Debug, Clone)]
+struct RequestTiming {
+    /// Host of the requested URL, for per-host summaries.
+    host: String,
+    /// Time spent in our resolver; zero if the request reused a connection
+    /// and never resolved the host.
+    dns_time: Duration,
+    /// Whether the request went out on a pooled connection. reqwest doesn't
+    /// report this, so it's inferred from the host not being resolved; a
+    /// URL with an IP address literal always looks reused.
+    connection_reused: bool,
+    /// Time from sending the request until the response headers arrived.
+    total_time: Duration,
+    status: StatusCode,
+    /// Body download, if `fetch_url` was asked to read it.
+    body: Option<BodyTiming>,
+}
+
+/// How many of a run's DNS lookups took longer than `--dns-slo`.
+#[derive(Debug, Clone, Copy)]
+struct SloReport {
+    budget: Duration,
+    violations: usize,
+    /// Requests that resolved their host; ones on a reused connection didn't.
+    lookups: usize,
+}
+
+impl SloReport {
+    fn new(timings: &[RequestTiming], budget: Duration) -> Self {
+        let lookups = timings.iter().filter(|t| !t.connection_reused);
+        SloReport {
+            budget,
+            violations: lookups.clone().filter(|t| t.dns_time > budget).count(),
+            lookups: lookups.count(),
+        }
+    }
+
+    /// Fraction of lookups over budget; zero if there weren't any.
+    fn rate(&self) -> f64 {
+        if self.lookups == 0 {
+            0.0
+        } else {
+            self.violations as f64 / self.lookups as f64
+        }
+    }
+}
+
+// Download `response`'s body, giving up with `BodyTooLarge` as soon as it's
+// known to exceed `limit` bytes, so a huge body never ends up in memory
+async fn read_capped(response: Response, limit: u64) -> Result<Vec<u8>, DnsCacheError> {
+    let too_large = DnsCacheError::BodyTooLarge { limit };
+    if response.content_length().is_some_and(|length| length > limit) {
+        return Err(too_large);
+    }
+
+    let mut body = Vec::new();
+    let mut chunks = response.bytes_stream();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        if (body.len() + chunk.len()) as u64 > limit {
+            return Err(too_large);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Size of a response body and how long it took to download after the
+/// headers arrived.
+#[derive(Debug, Clone, Copy)]
+struct BodyTiming {
+    /// Bytes as sent over the wire, compressed if the server compressed them.
+    bytes: u64,
+    download_time: Duration,
+    /// Set when the body was gzip or brotli encoded and we decompressed it.
+    decompressed: Option<Decompressed>,
+}
+
+/// Size of a compressed body once decompressed, and how long that took.
+#[derive(Debug, Clone, Copy)]
+struct Decompressed {
+    bytes: u64,
+    time: Duration,
+}
+
+/// Settings `fetch_url` applies to every request of a run.
+#[derive(Debug)]
+struct FetchOptions {
+    /// Extra headers sent with each request.
+    headers: HeaderMap,
+    retry: RetryPolicy,
+    /// Whether to download and time the response body.
+    read_body: bool,
+    /// Bodies bigger than this are abandoned with `BodyTooLarge`.
+    max_body_bytes: u64,
+    /// Whether to log per-request lines at debug instead of info level.
+    quiet: bool,
+    /// Lookups slower than this are logged as SLO violations.
+    dns_slo: Option<Duration>,
+    /// Host whose lookup counts as the request's DNS time, if not the URL's;
+    /// behind a proxy that's the proxy's host.
+    dns_host: Option<String>,
+}
+
+/// How `fetch_url` retries requests that fail to resolve or connect.
+#[derive(Debug, Clone, Copy)]
+struct RetryPolicy {
+    /// Retries after the first attempt; zero disables retrying.
+    max_retries: u32,
+    /// Delay before the first retry, doubled for each one after that.
+    base_backoff: Duration,
+    /// Fraction by which each backoff is randomly lengthened or shortened.
+    jitter: f64,
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self.base_backoff.saturating_mul(2u32.saturating_pow(attempt));
+        if self.jitter == 0.0 {
+            return backoff;
+        }
+        backoff.mul_f64(1.0 + rand::thread_rng().gen_range(-self.jitter..=self.jitter))
+    }
+}
+
 // Separate async function to fetch the URL, instrumented for tracing
-#[instrument(fields(url = %url))]
-async fn fetch_url(client: &Client, url: &str) -> Result<reqwest::Response, reqwest::Error> {
+#[instrument(skip(options), fields(url = %url, request_id = tracing::field::Empty))]
+async fn fetch_url(
+    client: &Client,
+    url: &Url,
+    options: &FetchOptions,
+) -> Result<RequestTiming, DnsCacheError> {
+    let FetchOptions { retry, read_body, max_body_bytes, quiet, dns_slo, .. } = *options;
+    // Tag every log line of this request, including the resolver's (which run
+    // inside this span), with an ID the server also sees. A caller-supplied
+    // X-Request-Id wins.
+    let mut headers = options.headers.clone();
+    let request_id = headers
+        .entry(REQUEST_ID)
+        .or_insert_with(|| {
+            HeaderValue::from_str(&Uuid::new_v4().to_string()).expect("UUIDs are valid header values")
+        })
+        .clone();
+    tracing::Span::current().record("request_id", request_id.to_str().unwrap_or("<non-ascii>"));
+
     debug!("Starting HTTP request to {}", url);
 
     // The DNS resolution happens inside our custom resolver
     let start = Instant::now();
-    let response = client.get(url).send().await?;
-    let duration = start.elapsed();
+    let sent = with_lookup_times(async {
+        let mut attempt = 0;
+        loop {
+            match client.get(url.clone()).headers(headers.clone()).send().await {
+                Ok(response) => return Ok(response),
+                // DNS failures surface as connect errors; anything else
+                // (including an HTTP error status, which isn't an Err at all)
+                // is final
+                Err(e) if e.is_connect() && attempt < retry.max_retries => {
+                    let backoff = retry.backoff(attempt);
+                    attempt += 1;
+                    warn!("Attempt {} failed: {}; retrying in {:?}", attempt, e, backoff);
+                    tokio::time::sleep(backoff).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    });
+    let (response, lookup_times) = sent.await;
+    let response = response?;
+    let total_time = start.elapsed();
+
+    // Pick up the lookup time the resolver recorded while connecting this
+    // request, if any. Every new connection starts with a lookup, so without
+    // one the request must have reused a connection, skipping the handshake
+    // too.
+    let lookup_time = options
+        .dns_host
+        .as_deref()
+        .or(url.host_str())
+        .and_then(|host| lookup_times.get(host).copied());
+    let connection_reused = lookup_time.is_none();
+    let dns_time = lookup_time.unwrap_or_default();
+    if let Some(slo) = dns_slo.filter(|&slo| dns_time > slo) {
+        warn!(
+            host = url.host_str(),
+            dns_time_ms = dns_time.as_secs_f64() * 1000.0,
+            slo_ms = slo.as_secs_f64() * 1000.0,
+            "DNS resolution exceeded the SLO"
+        );
+    }
 
     debug!("Response received");
-    info!("Total request time: {:?}", duration);
+    per_request!(quiet, "Total request time: {:?}", total_time);
+    if connection_reused {
+        per_request!(quiet, "Reused a pooled connection, no DNS lookup or handshake");
+    } else {
+        per_request!(
+            quiet,
+            "Opened a new connection: DNS {:?}, then {:?} to connect and get the response",
+            dns_time,
+            total_time.saturating_sub(dns_time)
+        );
+    }
+
+    // Timed separately so time to first byte and full download stay apart
+    let status = response.status();
+    let body = if read_body {
+        let encoding = response.headers().get(reqwest::header::CONTENT_ENCODING).cloned();
+        let body_start = Instant::now();
+        let body = read_capped(response, max_body_bytes).await?;
+        let download_time = body_start.elapsed();
+        let bytes = body.len() as u64;
+        per_request!(quiet, "Downloaded {} body bytes in {:?}", bytes, download_time);
+
+        let decompressed = decompress(encoding.as_ref(), &body).await;
+        if let Some(d) = decompressed {
+            per_request!(quiet, "Decompressed to {} bytes in {:?}", d.bytes, d.time);
+        }
+        Some(BodyTiming { bytes, download_time, decompressed })
+    } else {
+        None
+    };
+
+    Ok(RequestTiming {
+        host: url.host_str().unwrap_or_default().to_string(),
+        dns_time,
+        connection_reused,
+        total_time,
+        status,
+        body,
+    })
+}
+
+// Print the `--warmup` requests on their own, ahead of the summary
+fn print_warmup(warmup: &[RequestTiming]) {
+    for (i, t) in warmup.iter().enumerate() {
+        info!("Warm-up request #{}: total time {:?}, DNS time {:?}", i + 1, t.total_time, t.dns_time);
+        println!("Warm-up #{}: total time {:?}, DNS time {:?}", i + 1, t.total_time, t.dns_time);
+    }
+}
+
+// Print min/max/mean total request time across all requests, and
+// percentiles of the DNS lookups the resolver performed
+fn print_summary(timings: &[RequestTiming], latency: Option<LatencySummary>) {
+    if let (Some(min), Some(max)) = (
+        timings.iter().map(|t| t.total_time).min(),
+        timings.iter().map(|t| t.total_time).max(),
+    ) {
+        let mean = timings.iter().map(|t| t.total_time).sum::<Duration>() / timings.len() as u32;
+
+        info!("Total time over {} requests: min {:?}, max {:?}, mean {:?}", timings.len(), min, max, mean);
+        println!("Requests: {}", timings.len());
+        println!("Total time: min {:?}, max {:?}, mean {:?}", min, max, mean);
+
+        let (opened, reused) = connection_counts(timings);
+        info!(
+            connections_opened = opened,
+            connections_reused = reused,
+            "Connections: {} new, {} reused",
+            opened, reused
+        );
+        println!(
+            "Connections: {} new, {} reused (DNS consulted for {} of {} requests)",
+            opened, reused, opened, timings.len()
+        );
+    }
+
+    let hosts = host_summaries(timings);
+    if hosts.len() > 1 {
+        for h in &hosts {
+            info!(
+                "{}: {} requests, total time mean {:?}, DNS time mean {:?} over {} lookups",
+                h.host, h.count, h.mean, h.dns_mean, h.lookups
+            );
+            println!(
+                "  {}: {} requests, total time mean {:?}, DNS time mean {:?} over {} lookups",
+                h.host, h.count, h.mean, h.dns_mean, h.lookups
+            );
+        }
+    }
+
+    let bodies: Vec<BodyTiming> = timings.iter().filter_map(|t| t.body).collect();
+    if !bodies.is_empty() {
+        let bytes: u64 = bodies.iter().map(|b| b.bytes).sum();
+        let mean = bodies.iter().map(|b| b.download_time).sum::<Duration>() / bodies.len() as u32;
+
+        info!("Downloaded {} body bytes, mean download time {:?}", bytes, mean);
+        println!("Body bytes: {} total, download time mean {:?}", bytes, mean);
+
+        let decompressed: Vec<Decompressed> = bodies.iter().filter_map(|b| b.decompressed).collect();
+        if !decompressed.is_empty() {
+            let bytes: u64 = decompressed.iter().map(|d| d.bytes).sum();
+            let mean = decompressed.iter().map(|d| d.time).sum::<Duration>() / decompressed.len() as u32;
+
+            info!("Decompressed {} body bytes, mean decompression time {:?}", bytes, mean);
+            println!("Decompressed bytes: {} total, decompression time mean {:?}", bytes, mean);
+        }
+    }
+
+    if let Some(l) = latency {
+        info!(
+            "DNS time over {} lookups: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+            l.count, l.p50, l.p90, l.p99, l.max
+        );
+        println!("DNS lookups: {}", l.count);
+        println!("DNS time: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}", l.p50, l.p90, l.p99, l.max);
+    }
+}
+
+// Decompress a gzip or brotli `body` to measure its decoded size. Other
+// encodings, and bodies that fail to decode, are left as they are.
+#[cfg(feature = "compression")]
+async fn decompress(encoding: Option<&HeaderValue>, body: &[u8]) -> Option<Decompressed> {
+    use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder};
+    use tokio::io::AsyncReadExt;
+
+    let start = Instant::now();
+    let mut decoded = Vec::new();
+    let result = match encoding?.to_str().ok()?.trim() {
+        "gzip" | "x-gzip" => GzipDecoder::new(body).read_to_end(&mut decoded).await,
+        "br" => BrotliDecoder::new(body).read_to_end(&mut decoded).await,
+        _ => return None,
+    };
+    match result {
+        Ok(bytes) => Some(Decompressed { bytes: bytes as u64, time: start.elapsed() }),
+        Err(e) => {
+            warn!("Failed to decompress response body: {}", e);
+            None
+        }
+    }
+}
+
+// Without the `compression` feature we never ask for compressed bodies
+#[cfg(not(feature = "compression"))]
+async fn decompress(_encoding: Option<&HeaderValue>, _body: &[u8]) -> Option<Decompressed> {
+    None
+}
 
-    Ok(response)
-}
\ No newline at end of file
+// Print the caching and system runs of `--compare` side by side: request
+// times, and the DNS time of the requests that opened a new connection
+fn print_comparison(caching: &Run, system: &Run, format: OutputFormat) {
+    let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+    let runs = [("caching", caching), ("system", system)].map(|(name, run)| {
+        let totals: Vec<Duration> = run.timings.iter().map(|t| t.total_time).collect();
+        let lookups: Vec<Duration> = run
+            .timings
+            .iter()
+            .filter(|t| !t.connection_reused)
+            .map(|t| t.dns_time)
+            .collect();
+        RunSummary {
+            name,
+            succeeded: run.timings.len(),
+            failed: run.failures,
+            min: totals.iter().min().copied().unwrap_or_default(),
+            max: totals.iter().max().copied().unwrap_or_default(),
+            mean: totals.iter().sum::<Duration>() / totals.len().max(1) as u32,
+            lookups: lookups.len(),
+            dns_mean: lookups.iter().sum::<Duration>() / lookups.len().max(1) as u32,
+        }
+    });
+
+    for r in &runs {
+        info!(
+            "{} resolver: {} succeeded, {} failed, total time mean {:?}, DNS time mean {:?} over {} lookups",
+            r.name, r.succeeded, r.failed, r.mean, r.dns_mean, r.lookups
+        );
+    }
+    match format {
+        OutputFormat::Text => {
+            let row = |label: &str, value: &dyn Fn(&RunSummary) -> String| {
+                println!("{:<18} {:>14} {:>14}", label, value(&runs[0]), value(&runs[1]));
+            };
+            row("", &|r| r.name.to_string());
+            row("Succeeded", &|r| r.succeeded.to_string());
+            row("Failed", &|r| r.failed.to_string());
+            row("Total time min", &|r| format!("{:.2?}", r.min));
+            row("Total time mean", &|r| format!("{:.2?}", r.mean));
+            row("Total time max", &|r| format!("{:.2?}", r.max));
+            row("DNS lookups", &|r| r.lookups.to_string());
+            row("DNS time mean", &|r| format!("{:.2?}", r.dns_mean));
+        }
+        OutputFormat::Json => {
+            let run = |r: &RunSummary| {
+                json!({
+                    "succeeded": r.succeeded,
+                    "failed": r.failed,
+                    "total_time_ms": {
+                        "min": millis(r.min),
+                        "mean": millis(r.mean),
+                        "max": millis(r.max),
+                    },
+                    "dns_lookups": r.lookups,
+                    "dns_time_mean_ms": millis(r.dns_mean),
+                })
+            };
+            println!("{}", json!({ "caching": run(&runs[0]), "system": run(&runs[1]) }));
+        }
+    }
+}
+
+/// Aggregate timings of one `--compare` run.
+struct RunSummary {
+    name: &'static str,
+    succeeded: usize,
+    failed: u32,
+    min: Duration,
+    max: Duration,
+    mean: Duration,
+    /// Requests that resolved their host rather than reusing a connection.
+    lookups: usize,
+    /// Mean DNS time over those `lookups`.
+    dns_mean: Duration,
+}
+
+// How many of `timings` opened a new connection, and so resolved their host,
+// and how many reused a pooled one. Each request's lookups are recorded in
+// its own scope, so those of failed or cancelled requests aren't counted.
+fn connection_counts(timings: &[RequestTiming]) -> (usize, usize) {
+    let reused = timings.iter().filter(|t| t.connection_reused).count();
+    (timings.len() - reused, reused)
+}
+
+/// Aggregate timings of the requests to one host.
+struct HostSummary<'a> {
+    host: &'a str,
+    count: usize,
+    /// Mean total time.
+    mean: Duration,
+    /// Requests that resolved the host rather than reusing a connection.
+    lookups: usize,
+    /// Mean DNS time over those `lookups`.
+    dns_mean: Duration,
+}
+
+// Summarize `timings` per host, sorted by host
+fn host_summaries(timings: &[RequestTiming]) -> Vec<HostSummary<'_>> {
+    let mut by_host: BTreeMap<&str, Vec<&RequestTiming>> = BTreeMap::new();
+    for t in timings {
+        by_host.entry(&t.host).or_default().push(t);
+    }
+
+    by_host
+        .into_iter()
+        .map(|(host, timings)| {
+            let lookups: Vec<Duration> = timings
+                .iter()
+                .filter(|t| !t.connection_reused)
+                .map(|t| t.dns_time)
+                .collect();
+            HostSummary {
+                host,
+                count: timings.len(),
+                mean: timings.iter().map(|t| t.total_time).sum::<Duration>() / timings.len() as u32,
+                lookups: lookups.len(),
+                dns_mean: lookups.iter().sum::<Duration>() / lookups.len().max(1) as u32,
+            }
+        })
+        .collect()
+}
+
+// Print per-request timings and aggregate stats as one JSON object
+fn print_json(
+    run: &Run,
+    unexpected_status: usize,
+    slo: Option<SloReport>,
+    stats: CacheStats,
+    latency: Option<LatencySummary>,
+) {
+    let Run { warmup, timings, failures, interrupted } = run;
+    let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+    let request_json = |t: &RequestTiming| {
+        json!({
+            "status": t.status.as_u16(),
+            "host": t.host,
+            "dns_time_ms": millis(t.dns_time),
+            "connection_reused": t.connection_reused,
+            "total_time_ms": millis(t.total_time),
+            "body_bytes": t.body.map(|b| b.bytes),
+            "body_time_ms": t.body.map(|b| millis(b.download_time)),
+            "body_decompressed_bytes": t.body.and_then(|b| b.decompressed).map(|d| d.bytes),
+            "body_decompress_time_ms": t.body.and_then(|b| b.decompressed).map(|d| millis(d.time)),
+        })
+    };
+    let requests: Vec<_> = timings.iter().map(request_json).collect();
+    let warmup: Vec<_> = warmup.iter().map(request_json).collect();
+
+    let total_times = timings.iter().map(|t| t.total_time);
+    let summary = match (total_times.clone().min(), total_times.clone().max()) {
+        (Some(min), Some(max)) => json!({
+            "min_ms": millis(min),
+            "max_ms": millis(max),
+            "mean_ms": millis(total_times.sum::<Duration>() / timings.len() as u32),
+        }),
+        _ => serde_json::Value::Null,
+    };
+
+    let hosts: serde_json::Map<String, serde_json::Value> = host_summaries(timings)
+        .into_iter()
+        .map(|h| {
+            let summary = json!({
+                "count": h.count,
+                "mean_ms": millis(h.mean),
+                "dns_lookups": h.lookups,
+                "dns_mean_ms": millis(h.dns_mean),
+            });
+            (h.host.to_string(), summary)
+        })
+        .collect();
+
+    let dns_latency = match latency {
+        Some(l) => json!({
+            "lookups": l.count,
+            "p50_ms": millis(l.p50),
+            "p90_ms": millis(l.p90),
+            "p99_ms": millis(l.p99),
+            "max_ms": millis(l.max),
+        }),
+        None => serde_json::Value::Null,
+    };
+
+    let dns_slo = match slo {
+        Some(s) => json!({
+            "budget_ms": millis(s.budget),
+            "violations": s.violations,
+            "lookups": s.lookups,
+            "violation_rate": s.rate(),
+        }),
+        None => serde_json::Value::Null,
+    };
+
+    let (connections_opened, connections_reused) = connection_counts(timings);
+    let output = json!({
+        "warmup": warmup,
+        "requests": requests,
+        "count": timings.len(),
+        "failures": failures,
+        "unexpected_status": unexpected_status,
+        "interrupted": interrupted,
+        "total_time": summary,
+        "connections_opened": connections_opened,
+        "connections_reused": connections_reused,
+        "hosts": hosts,
+        "dns_cache": {
+            "hits": stats.hits,
+            "misses": stats.misses,
+        },
+        "dns_time": dns_latency,
+        "dns_slo": dns_slo,
+    });
+    println!("{}", output);
+}
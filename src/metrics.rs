@@ -0,0 +1,57 @@
+//! Prometheus metrics for DNS resolution, enabled by the `metrics` feature.
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
+
+// All metrics live in a crate-private registry so they don't collide with
+// whatever the application registers globally.
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+static RESOLUTION_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    let histogram = HistogramVec::new(
+        HistogramOpts::new(
+            "dns_resolution_duration_seconds",
+            "Time taken to resolve a hostname, including cache hits",
+        )
+        // Cache hits take microseconds, so start well below the default 5ms bucket
+        .buckets(prometheus::exponential_buckets(0.0001, 4.0, 8).unwrap()),
+        &["outcome"],
+    )
+    .unwrap();
+    REGISTRY.register(Box::new(histogram.clone())).unwrap();
+    histogram
+});
+
+static RESOLUTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let counter = IntCounterVec::new(
+        Opts::new("dns_resolutions_total", "Number of hostname resolutions by outcome"),
+        &["outcome"],
+    )
+    .unwrap();
+    REGISTRY.register(Box::new(counter.clone())).unwrap();
+    counter
+});
+
+/// Records one resolution and how long it took.
+pub(crate) fn observe(duration: Duration, success: bool) {
+    let outcome = if success { "success" } else { "failure" };
+    RESOLUTION_DURATION
+        .with_label_values(&[outcome])
+        .observe(duration.as_secs_f64());
+    RESOLUTIONS.with_label_values(&[outcome]).inc();
+}
+
+/// Renders all DNS metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    // Touch the metrics so they're registered even before the first lookup
+    LazyLock::force(&RESOLUTION_DURATION);
+    LazyLock::force(&RESOLUTIONS);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("encoding metrics to a Vec can't fail");
+    String::from_utf8(buffer).expect("Prometheus text format is UTF-8")
+}
@@ -0,0 +1,74 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hickory_resolver::lookup::Lookup;
+use hickory_resolver::lookup_ip::LookupIp;
+use hickory_resolver::proto::op::Query;
+use hickory_resolver::proto::rr::rdata::{A, AAAA};
+use hickory_resolver::proto::rr::{RData, Record, RecordType};
+use hickory_resolver::Name;
+use serde::{Deserialize, Serialize};
+
+use crate::stats::CacheEntry;
+
+/// A [`CacheEntry`] as written by `save_cache`. `Instant`s don't survive a
+/// restart, so the expiry is stored as wall-clock time.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct PersistedEntry {
+    host: String,
+    addrs: Vec<IpAddr>,
+    /// Milliseconds since the Unix epoch.
+    expires_at_ms: u64,
+}
+
+impl PersistedEntry {
+    pub(crate) fn from_entry(entry: &CacheEntry) -> Self {
+        let expires_at = SystemTime::now() + entry.ttl_remaining();
+        PersistedEntry {
+            host: entry.host.clone(),
+            addrs: entry.addrs.clone(),
+            expires_at_ms: expires_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        }
+    }
+
+    /// Converts back to a cache entry, or `None` if it has expired since it
+    /// was saved.
+    pub(crate) fn into_entry(self) -> Option<CacheEntry> {
+        let expires_at = UNIX_EPOCH + Duration::from_millis(self.expires_at_ms);
+        let remaining = expires_at.duration_since(SystemTime::now()).ok()?;
+        if remaining.is_zero() {
+            return None;
+        }
+        Some(CacheEntry {
+            host: self.host,
+            addrs: self.addrs,
+            valid_until: Instant::now() + remaining,
+        })
+    }
+}
+
+/// Builds the `LookupIp` hickory would have returned for a reloaded entry,
+/// so it flows through the same paths as a live answer.
+pub(crate) fn to_lookup(entry: &CacheEntry) -> Option<LookupIp> {
+    let name = Name::from_str(&entry.host).ok()?;
+    let ttl = entry.ttl_remaining().as_secs().try_into().unwrap_or(u32::MAX);
+    let records: Arc<[Record]> = entry
+        .addrs
+        .iter()
+        .map(|ip| {
+            let rdata = match *ip {
+                IpAddr::V4(ip) => RData::A(A(ip)),
+                IpAddr::V6(ip) => RData::AAAA(AAAA(ip)),
+            };
+            Record::from_rdata(name.clone(), ttl, rdata)
+        })
+        .collect();
+
+    let query = Query::query(name, RecordType::A);
+    Some(Lookup::new_with_deadline(query, records, entry.valid_until).into())
+}
@@ -0,0 +1,15 @@
+use serde::Serialize;
+
+/// One SRV record, as returned by
+/// [`HickoryDnsResolver::resolve_srv`](crate::HickoryDnsResolver::resolve_srv).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SrvRecord {
+    /// Host providing the service, without the trailing dot.
+    pub target: String,
+    /// Port the service listens on.
+    pub port: u16,
+    /// Lower values are tried first.
+    pub priority: u16,
+    /// Relative weight among records with the same priority.
+    pub weight: u16,
+}
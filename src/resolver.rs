@@ -0,0 +1,1614 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use futures::stream::{self, StreamExt};
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::proto::error::ProtoErrorKind;
+use hickory_resolver::proto::rr::{Record, RecordType};
+use hickory_resolver::lookup_ip::LookupIp;
+use hickory_resolver::Name;
+use hickory_resolver::TokioAsyncResolver;
+use ipnet::IpNet;
+use rand::seq::SliceRandom;
+use tokio::sync::{broadcast, Semaphore, SemaphorePermit};
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use crate::circuit::CircuitBreaker;
+use crate::deny::DenyList;
+use crate::error::DnsCacheError;
+use crate::events::{self, ResolveEvent, ResolveOutcome, DEFAULT_EVENT_CAPACITY};
+use crate::hosts::StaticHosts;
+use crate::ip_strategy::{IpStrategy, RoutableFamilies};
+use crate::lookup_times::record_lookup_time;
+use crate::persist::{self, PersistedEntry};
+use crate::records::SrvRecord;
+use crate::stats::{CacheCounters, CacheEntry, CacheStats, LatencySummary, LookupDurations};
+use crate::upstream::{self, ConfigSource, DnsProtocol, UpstreamDns};
+
+/// Maximum number of concurrent lookups issued by
+/// [`HickoryDnsResolver::prefetch`].
+pub const PREFETCH_CONCURRENCY: usize = 16;
+
+/// TTL reported by [`HickoryDnsResolver::resolve_host_with_ttl`] for
+/// records that don't carry a usable one.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// Custom DNS resolver that wraps hickory-resolver.
+///
+/// Answers are cached in memory by hickory for as long as their TTL allows,
+/// so only the first lookup of a host goes to the network. Cloning the
+/// resolver is cheap and every clone shares the same cache, stats and
+/// lookup history, so several reqwest clients given clones of one resolver
+/// (or the same [`shared`](Self::shared) `Arc`) share one DNS cache.
+#[derive(Clone)]
+pub struct HickoryDnsResolver {
+    /// The underlying hickory resolver, which owns the answer cache.
+    resolver: TokioAsyncResolver,
+    /// Resolver for the fallback upstream, tried when `resolver` fails.
+    fallback: Option<TokioAsyncResolver>,
+    /// The config and options `resolver` was built with, for
+    /// [`resolve_host_fresh`](Self::resolve_host_fresh) and
+    /// [`log_config`](Self::log_config).
+    config: Arc<(ResolverConfig, ResolverOpts)>,
+    /// Where the name servers in `config` came from.
+    config_source: ConfigSource,
+    /// Cache hit/miss counters, shared between clones.
+    counters: Arc<CacheCounters>,
+    /// Durations of the latest successful lookups, for
+    /// [`latency_summary`](Self::latency_summary).
+    lookup_durations: Arc<LookupDurations>,
+    /// Answers reloaded by [`load_cache`](Self::load_cache), served until they
+    /// expire since hickory's own cache can't be filled from outside.
+    preloaded: Arc<RwLock<HashMap<String, CacheEntry>>>,
+    /// Mirror of what hickory has cached, since it doesn't expose its cache:
+    /// the latest answer per host.
+    cache_mirror: Arc<RwLock<HashMap<String, CacheEntry>>>,
+    /// Address families to return and their order.
+    ip_strategy: IpStrategy,
+    /// Families to keep when only routable addresses are wanted.
+    routable: Option<RoutableFamilies>,
+    /// Whether to shuffle addresses before ordering them by family.
+    shuffle_addresses: bool,
+    /// Whether answers are DNSSEC-validated.
+    validate_dnssec: bool,
+    /// Limits how many lookups run at once, shared between clones.
+    lookup_permits: Option<Arc<Semaphore>>,
+    /// How long a failed lookup is remembered; zero disables this.
+    negative_ttl: Duration,
+    /// When each recently failed host last failed.
+    failures: Arc<Mutex<HashMap<String, Instant>>>,
+    /// Per-host circuit breaker, shared between clones.
+    circuit: Option<Arc<CircuitBreaker>>,
+    /// How long past expiry an answer may still be served if upstream can't
+    /// be reached; zero disables this.
+    serve_stale: Duration,
+    /// Called with each successfully resolved host and its addresses.
+    on_resolve: Option<ResolveHook>,
+    /// Whether lookups log where their time went.
+    timing_breakdown: bool,
+    /// Broadcasts every lookup to [`subscribe`](Self::subscribe) receivers.
+    events: broadcast::Sender<ResolveEvent>,
+    /// Whether names that aren't fully qualified are rejected.
+    strict_fqdn: bool,
+    /// Address ranges resolution results must not fall into.
+    deny: Arc<DenyList>,
+    /// Mappings from extra hosts files, checked before hickory.
+    extra_hosts: Arc<StaticHosts>,
+}
+
+impl HickoryDnsResolver {
+    /// Creates a resolver using hickory's default upstream (Google public
+    /// DNS) with a 1024-entry cache, a 3 second query timeout and 3 attempts
+    /// per query.
+    ///
+    /// This doesn't read the OS resolver configuration; use
+    /// [`with_upstream`](Self::with_upstream)`(UpstreamDns::System)` for that.
+    pub fn new() -> Self {
+        Self::builder().build().expect("the default configuration is valid")
+    }
+
+    /// Creates a resolver with the default options, wrapped in an `Arc` that
+    /// can be passed to `dns_resolver` on several `ClientBuilder`s.
+    pub fn shared() -> Arc<Self> {
+        Arc::new(Self::new())
+    }
+
+    /// Creates a resolver with the default options that sends its queries to
+    /// the given upstream instead.
+    pub fn with_upstream(upstream: UpstreamDns) -> Self {
+        Self::builder()
+            .upstream(upstream)
+            .build()
+            .expect("every upstream preset is a valid configuration")
+    }
+
+    /// Resolves `host` to its IP addresses through the cache.
+    ///
+    /// This is the same lookup the `reqwest::dns::Resolve` impl performs, so
+    /// it's timed, logged and counted in [`stats`](Self::stats) the same way,
+    /// but the addresses are returned as-is instead of as `SocketAddr`s.
+    ///
+    /// Hosts mapped by an [`extra_hosts`](HickoryDnsResolverBuilder::extra_hosts)
+    /// file are answered from that mapping without a lookup. Either way, the
+    /// [`on_resolve`](HickoryDnsResolverBuilder::on_resolve) hook sees the
+    /// addresses before they're returned.
+    ///
+    /// An internationalized `host` such as `münchen.de` is looked up, cached
+    /// and reported by its punycode form, `xn--mnchen-3ya.de`; ASCII hosts are
+    /// used as given. Fails with [`DnsCacheError::InvalidHostname`] if `host`
+    /// can't be converted, or with [`DnsCacheError::NotFullyQualified`] if
+    /// [`strict_fqdn`](HickoryDnsResolverBuilder::strict_fqdn) is on and it's
+    /// a relative name.
+    pub async fn resolve_host(&self, host: &str) -> Result<Vec<IpAddr>, DnsCacheError> {
+        let host = &*self.host_name(host)?;
+        let mut ips = match self.static_host(host) {
+            Some(ips) => ips,
+            None => {
+                let lookup = self.lookup_ip(host).await.map_err(|e| self.classify(e))?;
+
+                let mut ips: Vec<IpAddr> = lookup.iter().collect();
+                self.arrange(&mut ips);
+                debug!("Resolved {} to {} addresses", host, ips.len());
+
+                if ips.is_empty() {
+                    return Err(self.no_matching_addresses(host).into());
+                }
+                ips
+            }
+        };
+        self.screen(host, &mut ips, |ip| *ip)?;
+
+        if let Some(on_resolve) = &self.on_resolve {
+            on_resolve(host, &ips);
+        }
+        Ok(ips)
+    }
+
+    /// Like [`resolve_host`](Self::resolve_host), but fails with
+    /// [`DnsCacheError::Timeout`] once `deadline` passes, even if the
+    /// resolver's own timeout and attempts would allow longer.
+    ///
+    /// A timed-out lookup is cancelled rather than left running, so it gives
+    /// back its [`max_concurrent_lookups`](HickoryDnsResolverBuilder::max_concurrent_lookups)
+    /// permit straight away.
+    pub async fn resolve_host_timeout(&self, host: &str, deadline: Duration) -> Result<Vec<IpAddr>, DnsCacheError> {
+        match tokio::time::timeout(deadline, self.resolve_host(host)).await {
+            Ok(result) => result,
+            Err(_) => {
+                info!(host, deadline_ms = millis(deadline), outcome = "timeout", "DNS resolution timed out");
+                Err(DnsCacheError::Timeout {
+                    host: host.to_string(),
+                    deadline,
+                })
+            }
+        }
+    }
+
+    /// Like [`resolve_host`](Self::resolve_host), but returns socket
+    /// addresses with `port` set, ready to connect to.
+    pub async fn resolve_host_port(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, DnsCacheError> {
+        let ips = self.resolve_host(host).await?;
+        Ok(ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect())
+    }
+
+    /// Like [`resolve_host`](Self::resolve_host), but pairs each address with
+    /// the time its record has left to live.
+    ///
+    /// For cached answers this is the remaining TTL, not the original one.
+    /// Records without a usable TTL report [`DEFAULT_TTL`] instead of zero,
+    /// as do addresses from an extra hosts file.
+    pub async fn resolve_host_with_ttl(&self, host: &str) -> Result<Vec<(IpAddr, Duration)>, DnsCacheError> {
+        let host = &*self.host_name(host)?;
+        let mut entries = match self.static_host(host) {
+            Some(ips) => ips.into_iter().map(|ip| (ip, DEFAULT_TTL)).collect(),
+            None => self.lookup_with_ttl(host).await?,
+        };
+        self.screen(host, &mut entries, |(ip, _)| *ip)?;
+
+        if let Some(on_resolve) = &self.on_resolve {
+            let ips: Vec<IpAddr> = entries.iter().map(|(ip, _)| *ip).collect();
+            on_resolve(host, &ips);
+        }
+        Ok(entries)
+    }
+
+    // The lookup behind `resolve_host_with_ttl`, before screening
+    async fn lookup_with_ttl(&self, host: &str) -> Result<Vec<(IpAddr, Duration)>, DnsCacheError> {
+        let lookup = self.lookup_ip(host).await.map_err(|e| self.classify(e))?;
+
+        // The lookup as a whole expires when its shortest-lived record does
+        let remaining = lookup.valid_until().saturating_duration_since(Instant::now());
+        let mut entries: Vec<(IpAddr, Duration)> = lookup
+            .as_lookup()
+            .records()
+            .iter()
+            .filter_map(|record| {
+                let ip = record.data()?.ip_addr()?;
+                let ttl = Duration::from_secs(record.ttl().into()).min(remaining);
+                Some((ip, if ttl.is_zero() { DEFAULT_TTL } else { ttl }))
+            })
+            .collect();
+        self.filter_families(&mut entries, |(ip, _)| *ip);
+        debug!("Resolved {} to {} addresses", host, entries.len());
+
+        if entries.is_empty() {
+            return Err(self.no_matching_addresses(host).into());
+        }
+        Ok(entries)
+    }
+
+    /// Resolves `host` with a fresh query upstream, bypassing the cache.
+    ///
+    /// The lookup goes through a short-lived resolver with the same
+    /// configuration but no cache, so it measures true cold resolution. Its
+    /// answer isn't added to the shared cache, and it isn't counted in
+    /// [`stats`](Self::stats), [`latency_summary`](Self::latency_summary) or
+    /// the negative cache. Extra hosts mappings, the denylist and the
+    /// [`on_resolve`](HickoryDnsResolverBuilder::on_resolve) hook still apply.
+    pub async fn resolve_host_fresh(&self, host: &str) -> Result<Vec<IpAddr>, DnsCacheError> {
+        let host = &*self.host_name(host)?;
+        let mut ips = match self.static_host(host) {
+            Some(ips) => ips,
+            None => self.lookup_fresh(host).await?,
+        };
+        self.screen(host, &mut ips, |ip| *ip)?;
+
+        if let Some(on_resolve) = &self.on_resolve {
+            on_resolve(host, &ips);
+        }
+        Ok(ips)
+    }
+
+    // The uncached lookup behind `resolve_host_fresh`, before screening
+    async fn lookup_fresh(&self, host: &str) -> Result<Vec<IpAddr>, DnsCacheError> {
+        let (config, opts) = &*self.config;
+        let mut opts = opts.clone();
+        opts.cache_size = 0;
+        let resolver = TokioAsyncResolver::tokio(config.clone(), opts);
+
+        let start = Instant::now();
+        debug!("Resolving hostname without cache: {}", host);
+        let _permit = self.lookup_permit().await;
+        let lookup = resolver.lookup_ip(host).await.inspect_err(|e| {
+            info!(
+                host,
+                duration_ms = millis(start.elapsed()),
+                addr_count = 0,
+                outcome = "failure",
+                error = %e,
+                "Uncached DNS resolution failed"
+            );
+        })
+        .map_err(|e| self.classify(e))?;
+        info!(
+            host,
+            duration_ms = millis(start.elapsed()),
+            addr_count = lookup.iter().count(),
+            outcome = "uncached",
+            "Uncached DNS resolution succeeded"
+        );
+
+        let mut ips: Vec<IpAddr> = lookup.iter().collect();
+        self.filter_families(&mut ips, |ip| *ip);
+        debug!("Resolved {} to {} addresses", host, ips.len());
+
+        if ips.is_empty() {
+            return Err(self.no_matching_addresses(host).into());
+        }
+        Ok(ips)
+    }
+
+    /// Returns the CNAME chain `host` resolves through, starting with the
+    /// queried name and ending at the name that has the A/AAAA records.
+    ///
+    /// A host without CNAMEs yields just itself. Names are returned without
+    /// the trailing dot. This is an ordinary lookup, so it's cached, timed and
+    /// counted like [`resolve_host`](Self::resolve_host), and an
+    /// internationalized `host` is traced from its punycode form.
+    pub async fn trace_cname(&self, host: &str) -> Result<Vec<String>, DnsCacheError> {
+        let host = &*self.host_name(host)?;
+        if self.static_host(host).is_some() {
+            return Ok(vec![host.to_string()]);
+        }
+
+        let lookup = self.lookup_ip(host).await.map_err(|e| self.classify(e))?;
+        let lookup = lookup.as_lookup();
+        let canonical = |name: &Name| name.to_lowercase().to_utf8().trim_end_matches('.').to_string();
+
+        // hickory keeps the intermediate CNAME records alongside the answers
+        let aliases: HashMap<String, String> = lookup
+            .records()
+            .iter()
+            .filter_map(|record| {
+                let target = record.data()?.as_cname()?;
+                Some((canonical(record.name()), canonical(&target.0)))
+            })
+            .collect();
+
+        let mut chain = vec![canonical(lookup.query().name())];
+        while let Some(next) = aliases.get(chain.last().unwrap()) {
+            // Bail out of a looping chain rather than following it forever
+            if chain.contains(next) {
+                break;
+            }
+            chain.push(next.clone());
+        }
+        debug!("CNAME chain for {}: {}", host, chain.join(" -> "));
+        Ok(chain)
+    }
+
+    /// Looks up the SRV records for `name`, e.g. `_http._tcp.example.com`,
+    /// sorted by priority and then by descending weight.
+    ///
+    /// Answers are cached like address lookups but aren't counted in
+    /// [`stats`](Self::stats).
+    pub async fn resolve_srv(&self, name: &str) -> Result<Vec<SrvRecord>, DnsCacheError> {
+        let start = Instant::now();
+        debug!("Resolving SRV records for {}", name);
+        let lookup = {
+            let _permit = self.lookup_permit().await;
+            self.resolver.srv_lookup(name).await.map_err(|e| {
+                info!("Failed to resolve SRV records for {}: {}", name, e);
+                self.classify(e)
+            })?
+        };
+
+        let mut records: Vec<SrvRecord> = lookup
+            .iter()
+            .map(|srv| SrvRecord {
+                target: srv.target().to_utf8().trim_end_matches('.').to_string(),
+                port: srv.port(),
+                priority: srv.priority(),
+                weight: srv.weight(),
+            })
+            .collect();
+        records.sort_by_key(|srv| (srv.priority, std::cmp::Reverse(srv.weight)));
+        info!("SRV resolution for {} took {:?}, {} records", name, start.elapsed(), records.len());
+        Ok(records)
+    }
+
+    /// Looks up the TXT records for `name`, one string per record.
+    ///
+    /// A record's character strings are concatenated, as SPF and DKIM
+    /// expect. Data that isn't valid UTF-8 is converted lossily rather than
+    /// failing the lookup.
+    pub async fn resolve_txt(&self, name: &str) -> Result<Vec<String>, DnsCacheError> {
+        let start = Instant::now();
+        debug!("Resolving TXT records for {}", name);
+        let lookup = {
+            let _permit = self.lookup_permit().await;
+            self.resolver.txt_lookup(name).await.map_err(|e| {
+                info!("Failed to resolve TXT records for {}: {}", name, e);
+                self.classify(e)
+            })?
+        };
+
+        let records: Vec<String> = lookup
+            .iter()
+            .map(|txt| String::from_utf8_lossy(&txt.txt_data().concat()).into_owned())
+            .collect();
+        info!("TXT resolution for {} took {:?}, {} records", name, start.elapsed(), records.len());
+        Ok(records)
+    }
+
+    /// Looks up the PTR names of `ip`, e.g. to label addresses in logs.
+    ///
+    /// An address without a PTR record yields an empty list, not an error.
+    /// Names keep their trailing dot, as returned by the server.
+    pub async fn reverse_lookup(&self, ip: IpAddr) -> Result<Vec<String>, DnsCacheError> {
+        let start = Instant::now();
+        debug!("Reverse resolving {}", ip);
+        let result = {
+            let _permit = self.lookup_permit().await;
+            self.resolver.reverse_lookup(ip).await
+        };
+
+        let names: Vec<String> = match result {
+            Ok(lookup) => lookup.iter().map(ToString::to_string).collect(),
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Vec::new(),
+            Err(e) => {
+                info!("Failed to reverse resolve {}: {}", ip, e);
+                return Err(self.classify(e));
+            }
+        };
+        info!("Reverse resolution for {} took {:?}, {} names", ip, start.elapsed(), names.len());
+        Ok(names)
+    }
+
+    /// Looks up the records of any `record_type` for `name`, e.g. MX, NS or
+    /// CAA, for types without a dedicated helper.
+    ///
+    /// The records are returned as received, each with its TTL, including
+    /// any CNAMEs the name resolved through. Queries are always class IN.
+    pub async fn lookup(&self, name: &str, record_type: RecordType) -> Result<Vec<Record>, DnsCacheError> {
+        let start = Instant::now();
+        debug!("Resolving {} records for {}", record_type, name);
+        let lookup = {
+            let _permit = self.lookup_permit().await;
+            self.resolver.lookup(name, record_type).await.map_err(|e| {
+                info!("Failed to resolve {} records for {}: {}", record_type, name, e);
+                self.classify(e)
+            })?
+        };
+
+        let records = lookup.records().to_vec();
+        info!("{} resolution for {} took {:?}, {} records", record_type, name, start.elapsed(), records.len());
+        Ok(records)
+    }
+
+    // Shared address lookup path: every lookup is timed, logged and counted here
+    async fn lookup_ip(&self, host: &str) -> Result<LookupIp, ResolveError> {
+        let start = Instant::now();
+        debug!("Resolving hostname: {}", host);
+
+        if let Some(failed_at) = self.recent_failure(host, start) {
+            debug!("Skipping lookup of {}, it failed {:?} ago", host, start - failed_at);
+            let error = format!("resolution of {} failed recently, not retrying yet", host).into();
+            return self.stale_or(host, error, start);
+        }
+        if let Some(circuit) = &self.circuit {
+            if let Err(retry_in) = circuit.allow(host, start) {
+                debug!("Circuit for {} is open, failing fast", host);
+                let error = format!("circuit for {} is open, next probe in {:?}", host, retry_in).into();
+                return self.stale_or(host, error, start);
+            }
+        }
+
+        // Dropping this future, e.g. when the caller times out, drops the
+        // query along with it; the guard logs that it happened
+        let mut cancelled = CancelGuard { host, start, events: &self.events, armed: true };
+        let mut timing = QueryTiming::default();
+        let result = self.query(host, &mut timing).await;
+        let breakdown = self.timing_breakdown.then_some(timing);
+        cancelled.armed = false;
+
+        match result {
+            Ok((lookup, answered_by)) => {
+                if !self.negative_ttl.is_zero() {
+                    self.failures.lock().unwrap().remove(host);
+                }
+                if let Some(circuit) = &self.circuit {
+                    circuit.record_success(host);
+                }
+                let duration = start.elapsed();
+                let hit = self.counters.record(host, lookup.valid_until());
+                record_lookup_time(host, duration);
+                self.lookup_durations.record(duration);
+                let entry = CacheEntry {
+                    host: host.to_string(),
+                    addrs: lookup.iter().collect(),
+                    valid_until: lookup.valid_until(),
+                };
+                self.cache_mirror.write().unwrap().insert(host.to_string(), entry);
+                info!(
+                    host,
+                    duration_ms = millis(duration),
+                    addr_count = lookup.iter().count(),
+                    outcome = if hit { "hit" } else { "miss" },
+                    resolver = answered_by,
+                    queue_ms = breakdown.map(|t| millis(t.queued)),
+                    query_ms = breakdown.map(|t| millis(t.query)),
+                    local_ms = breakdown.map(|t| millis(t.local(duration))),
+                    "DNS resolution succeeded"
+                );
+                let outcome = if hit { ResolveOutcome::Hit } else { ResolveOutcome::Miss };
+                events::emit(&self.events, host, duration, outcome, lookup.iter().count());
+                #[cfg(feature = "metrics")]
+                crate::metrics::observe(duration, true);
+
+                Ok(lookup)
+            }
+            Err(e) => {
+                let duration = start.elapsed();
+                info!(
+                    host,
+                    duration_ms = millis(duration),
+                    addr_count = 0,
+                    outcome = "failure",
+                    error = %e,
+                    queue_ms = breakdown.map(|t| millis(t.queued)),
+                    query_ms = breakdown.map(|t| millis(t.query)),
+                    local_ms = breakdown.map(|t| millis(t.local(duration))),
+                    "DNS resolution failed"
+                );
+                events::emit(&self.events, host, start.elapsed(), ResolveOutcome::Failure, 0);
+                if !self.negative_ttl.is_zero() {
+                    self.failures
+                        .lock()
+                        .unwrap()
+                        .insert(host.to_string(), Instant::now());
+                }
+                self.counters.record_failure();
+                // A name that doesn't exist is an answer, not an outage, so
+                // it also closes the circuit after a probe
+                if let Some(circuit) = &self.circuit {
+                    if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) {
+                        circuit.record_success(host);
+                    } else {
+                        circuit.record_failure(host, Instant::now());
+                    }
+                }
+                #[cfg(feature = "metrics")]
+                crate::metrics::observe(start.elapsed(), false);
+                self.stale_or(host, e, start)
+            }
+        }
+    }
+
+    // The last answer for `host` if upstream couldn't give a fresh one and it
+    // expired no more than `serve_stale` ago, otherwise `error`. A name that
+    // doesn't exist is an answer, not an outage, so it's never papered over.
+    fn stale_or(&self, host: &str, error: ResolveError, start: Instant) -> Result<LookupIp, ResolveError> {
+        if self.serve_stale.is_zero() || matches!(error.kind(), ResolveErrorKind::NoRecordsFound { .. }) {
+            return Err(error);
+        }
+        let stale = {
+            let mirror = self.cache_mirror.read().unwrap();
+            mirror
+                .get(host)
+                .filter(|entry| self.within_stale_window(entry, Instant::now()))
+                .cloned()
+        };
+        let Some(entry) = stale else {
+            return Err(error);
+        };
+        let Some(lookup) = persist::to_lookup(&entry) else {
+            return Err(error);
+        };
+
+        warn!(
+            host,
+            expired_ms_ago = millis(entry.valid_until.elapsed()),
+            error = %error,
+            "Upstream DNS failed, serving stale answer"
+        );
+        // Still a lookup as far as the caller's request timing is concerned
+        record_lookup_time(host, start.elapsed());
+        Ok(lookup)
+    }
+
+    // Whether `entry` is unexpired, or expired no more than `serve_stale` ago
+    fn within_stale_window(&self, entry: &CacheEntry, now: Instant) -> bool {
+        entry
+            .valid_until
+            .checked_add(self.serve_stale)
+            .is_none_or(|deadline| deadline > now)
+    }
+
+    // `host` as it's looked up: in punycode, and checked to be fully
+    // qualified in strict mode
+    fn host_name<'a>(&self, host: &'a str) -> Result<Cow<'a, str>, DnsCacheError> {
+        let host = to_ascii(host)?;
+        if self.strict_fqdn && !is_fqdn(&host) {
+            debug!(host = %host, "Rejected name that isn't fully qualified");
+            return Err(DnsCacheError::NotFullyQualified { host: host.into_owned() });
+        }
+        Ok(host)
+    }
+
+    // Drops addresses in denied ranges. If that leaves none, the host is
+    // blocked outright rather than reported as having no addresses.
+    fn screen<T>(&self, host: &str, items: &mut Vec<T>, ip_of: impl Fn(&T) -> IpAddr) -> Result<(), DnsCacheError> {
+        if self.deny.is_empty() {
+            return Ok(());
+        }
+        let denied: Vec<IpAddr> = items.iter().map(&ip_of).filter(|&ip| self.deny.denies(ip)).collect();
+        if denied.is_empty() {
+            return Ok(());
+        }
+
+        items.retain(|item| !self.deny.denies(ip_of(item)));
+        if items.is_empty() {
+            warn!(host, addrs = ?denied, "Blocked resolution, every address is in a denied range");
+            return Err(DnsCacheError::Blocked {
+                host: host.to_string(),
+                addrs: denied,
+            });
+        }
+        debug!(host, addrs = ?denied, "Dropped addresses in denied ranges");
+        Ok(())
+    }
+
+    // Addresses for `host` from the extra hosts files, filtered by the IP
+    // strategy. Hosts with no address of an allowed family fall through to a
+    // normal lookup.
+    fn static_host(&self, host: &str) -> Option<Vec<IpAddr>> {
+        if self.extra_hosts.is_empty() {
+            return None;
+        }
+
+        let mut ips = self.extra_hosts.get(host)?.to_vec();
+        self.arrange(&mut ips);
+        if ips.is_empty() {
+            return None;
+        }
+        debug!("Resolved {} to {} addresses from extra hosts", host, ips.len());
+        Some(ips)
+    }
+
+    // Filters and orders addresses by the IP strategy, shuffling them first
+    // if enabled. The strategy's sort is stable, so a shuffle only changes
+    // the order within each address family.
+    fn arrange(&self, ips: &mut Vec<IpAddr>) {
+        if self.shuffle_addresses {
+            ips.shuffle(&mut rand::thread_rng());
+        }
+        self.filter_families(ips, |ip| *ip);
+    }
+
+    // Applies the IP strategy, then drops addresses this host can't route to
+    // if asked to
+    fn filter_families<T>(&self, items: &mut Vec<T>, ip_of: impl Fn(&T) -> IpAddr) {
+        self.ip_strategy.apply(items, &ip_of);
+        if let Some(routable) = self.routable {
+            items.retain(|item| routable.allows(ip_of(item)));
+        }
+    }
+
+    // Queries the primary upstream, then the fallback if the primary couldn't
+    // answer. Returns which of the two answered.
+    async fn query(&self, host: &str, timing: &mut QueryTiming) -> Result<(LookupIp, &'static str), ResolveError> {
+        if let Some(lookup) = self.preloaded_lookup(host) {
+            return Ok((lookup, "persisted"));
+        }
+
+        let queue_start = Instant::now();
+        let _permit = self.lookup_permit().await;
+        timing.queued = queue_start.elapsed();
+        let query_start = Instant::now();
+        let result = self.query_upstream(host).await;
+        timing.query = query_start.elapsed();
+        result
+    }
+
+    // Asks the primary upstream, or hickory's cache of its answers, then the
+    // fallback if there is one and the primary failed
+    async fn query_upstream(&self, host: &str) -> Result<(LookupIp, &'static str), ResolveError> {
+        let error = match self.resolver.lookup_ip(host).await {
+            Ok(lookup) => return Ok((lookup, "primary")),
+            Err(e) => e,
+        };
+        let Some(fallback) = &self.fallback else {
+            return Err(error);
+        };
+        // The primary answered, there's just nothing there; asking another
+        // upstream won't change that
+        if matches!(error.kind(), ResolveErrorKind::NoRecordsFound { .. }) {
+            return Err(error);
+        }
+
+        warn!("Primary resolver failed for {}: {}, trying fallback", host, error);
+        let lookup = fallback.lookup_ip(host).await?;
+        Ok((lookup, "fallback"))
+    }
+
+    // A reloaded answer for `host`, if there's one that hasn't expired
+    fn preloaded_lookup(&self, host: &str) -> Option<LookupIp> {
+        let preloaded = self.preloaded.read().unwrap();
+        let entry = preloaded.get(host)?;
+        if entry.valid_until > Instant::now() {
+            return persist::to_lookup(entry);
+        }
+        drop(preloaded);
+
+        // Expired, so from now on hickory answers for this host
+        self.preloaded.write().unwrap().remove(host);
+        None
+    }
+
+    // Waits for a lookup slot if the number of concurrent lookups is limited.
+    // The permit is released when dropped, however the lookup ends, including
+    // when the caller gives up on it.
+    async fn lookup_permit(&self) -> Option<SemaphorePermit<'_>> {
+        let permits = self.lookup_permits.as_ref()?;
+        Some(permits.acquire().await.expect("lookup semaphore is never closed"))
+    }
+
+    // When `host` last failed, if that's still within the negative TTL
+    fn recent_failure(&self, host: &str, now: Instant) -> Option<Instant> {
+        if self.negative_ttl.is_zero() {
+            return None;
+        }
+
+        let mut failures = self.failures.lock().unwrap();
+        match failures.get(host) {
+            Some(&failed_at) if now.duration_since(failed_at) < self.negative_ttl => Some(failed_at),
+            Some(_) => {
+                // Expired, so the next lookup goes to the network again
+                failures.remove(host);
+                None
+            }
+            None => None,
+        }
+    }
+
+    // Tells DNSSEC validation failures apart from other resolution errors.
+    // hickory reports them as protocol errors carrying a message, whereas
+    // network trouble has its own error kinds.
+    fn classify(&self, error: ResolveError) -> DnsCacheError {
+        let validation_failure = self.validate_dnssec
+            && matches!(error.kind(), ResolveErrorKind::Proto(e) if matches!(
+                e.kind(),
+                ProtoErrorKind::Message(_) | ProtoErrorKind::Msg(_) | ProtoErrorKind::RrsigsNotPresent { .. }
+            ));
+        if validation_failure {
+            DnsCacheError::DnssecValidation(error)
+        } else {
+            DnsCacheError::Resolve(error)
+        }
+    }
+
+    // Error for a lookup whose addresses were all removed by the IP strategy
+    fn no_matching_addresses(&self, host: &str) -> ResolveError {
+        let constraints = match self.routable {
+            Some(routable) => format!(
+                "IP strategy {:?} and routable families (IPv4: {}, IPv6: {})",
+                self.ip_strategy, routable.ipv4, routable.ipv6
+            ),
+            None => format!("IP strategy {:?}", self.ip_strategy),
+        };
+        info!("No addresses for {} match {}", host, constraints);
+        format!("no addresses for {} match {}", host, constraints).into()
+    }
+
+    /// Resolves a batch of hosts concurrently so their answers are already
+    /// cached by the time real requests need them.
+    ///
+    /// At most [`PREFETCH_CONCURRENCY`] lookups are in flight at once. A
+    /// failure for one host doesn't affect the others; each host's outcome is
+    /// reported in the returned map.
+    pub async fn prefetch(&self, hosts: &[&str]) -> HashMap<String, Result<Vec<IpAddr>, DnsCacheError>> {
+        stream::iter(hosts)
+            .map(|&host| async move { (host.to_string(), self.resolve_host(host).await) })
+            .buffer_unordered(PREFETCH_CONCURRENCY)
+            .collect()
+            .await
+    }
+
+    /// Checks a URL whose host is an IP address literal, such as
+    /// `http://169.254.169.254/`, against the denied ranges, failing with
+    /// [`DnsCacheError::Blocked`] if it's in one.
+    ///
+    /// reqwest only calls the resolver for hostnames, so requests to address
+    /// literals never pass through the denylist otherwise. URLs with a
+    /// hostname always pass here; their addresses are screened when they
+    /// resolve.
+    pub fn check_url(&self, url: &reqwest::Url) -> Result<(), DnsCacheError> {
+        let Some(host) = url.host_str() else {
+            return Ok(());
+        };
+        // IPv6 literals keep their brackets in URLs
+        let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() else {
+            return Ok(());
+        };
+        self.screen(host, &mut vec![ip], |ip| *ip)
+    }
+
+    /// A redirect policy for clients using this resolver that refuses, with
+    /// [`DnsCacheError::Blocked`], redirects to an address literal
+    /// [`check_url`](Self::check_url) rejects, and otherwise follows up to
+    /// 10 redirects like reqwest's default. Without denied ranges it's
+    /// reqwest's default.
+    pub fn redirect_policy(&self) -> reqwest::redirect::Policy {
+        if self.deny.is_empty() {
+            return reqwest::redirect::Policy::default();
+        }
+        let this = self.clone();
+        reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= 10 {
+                return attempt.error("too many redirects");
+            }
+            match this.check_url(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(e),
+            }
+        })
+    }
+
+    /// Returns how many lookups so far were answered from the cache versus
+    /// sent upstream, and how many failed there. Lookups skipped because of
+    /// the [negative TTL](HickoryDnsResolverBuilder::negative_ttl) aren't
+    /// counted.
+    pub fn stats(&self) -> CacheStats {
+        self.counters.snapshot()
+    }
+
+    /// Returns p50/p90/p99 of the durations of the latest
+    /// [`LATENCY_WINDOW`](crate::LATENCY_WINDOW) successful lookups, cache
+    /// hits included, or `None` if there haven't been any.
+    pub fn latency_summary(&self) -> Option<LatencySummary> {
+        self.lookup_durations.summary()
+    }
+
+    /// Returns a receiver for an event per lookup from now on, whether it hit
+    /// the cache, went upstream, failed or was cancelled, e.g. to drive a
+    /// live dashboard.
+    ///
+    /// Events are only built while someone is subscribed, and sending them
+    /// never blocks a lookup: a receiver that falls more than
+    /// [`event_capacity`](HickoryDnsResolverBuilder::event_capacity) events
+    /// behind misses the oldest ones and gets
+    /// [`RecvError::Lagged`](broadcast::error::RecvError::Lagged). Every
+    /// clone of the resolver feeds the same subscribers.
+    pub fn subscribe(&self) -> broadcast::Receiver<ResolveEvent> {
+        self.events.subscribe()
+    }
+
+    /// Logs the [`stats`](Self::stats) and
+    /// [`latency_summary`](Self::latency_summary) so far as a single
+    /// structured event, e.g. on shutdown.
+    pub fn log_summary(&self) {
+        let stats = self.stats();
+        let latency = self.latency_summary();
+        let ms = |f: fn(&LatencySummary) -> Duration| latency.as_ref().map(|l| millis(f(l)));
+        info!(
+            lookups = stats.hits + stats.misses + stats.failures,
+            hits = stats.hits,
+            misses = stats.misses,
+            failures = stats.failures,
+            hit_ratio = stats.hit_ratio(),
+            mean_ms = ms(|l| l.mean),
+            p50_ms = ms(|l| l.p50),
+            p90_ms = ms(|l| l.p90),
+            p99_ms = ms(|l| l.p99),
+            max_ms = ms(|l| l.max),
+            "DNS resolver summary"
+        );
+    }
+
+    /// Where the upstream name servers came from. Worth checking with
+    /// [`UpstreamDns::System`], which falls back to Google public DNS when
+    /// the OS configuration can't be read, e.g. in a minimal container.
+    pub fn config_source(&self) -> ConfigSource {
+        self.config_source
+    }
+
+    /// How many answers the cache holds before it evicts the least recently
+    /// used one. Each record type of a host takes its own entry, so a host
+    /// looked up for both IPv4 and IPv6 addresses can take two.
+    pub fn cache_size(&self) -> usize {
+        self.config.1.cache_size
+    }
+
+    /// The underlying hickory resolver, for lookups this type doesn't wrap.
+    ///
+    /// It shares the same answer cache, so what it looks up is cached for
+    /// this resolver too and vice versa. But queries made through it directly
+    /// bypass everything this type adds around hickory: they aren't counted
+    /// in [`stats`](Self::stats) or [`latency_summary`](Self::latency_summary),
+    /// don't show up in [`cached_entries`](Self::cached_entries) or
+    /// [`subscribe`](Self::subscribe) events, never reach the
+    /// [`on_resolve`](HickoryDnsResolverBuilder::on_resolve) hook, and skip
+    /// the fallback upstream, extra hosts, denylist, negative caching and
+    /// other per-lookup options.
+    pub fn inner(&self) -> &TokioAsyncResolver {
+        &self.resolver
+    }
+
+    /// Logs the effective configuration as one structured `info` event:
+    /// upstream name servers, cache size, timeout, attempts, IP strategy and
+    /// the other settings that shape caching.
+    pub fn log_config(&self) {
+        let (config, opts) = &*self.config;
+        let name_servers: Vec<String> = config
+            .name_servers()
+            .iter()
+            .map(|ns| format!("{}/{}", ns.socket_addr, ns.protocol))
+            .collect();
+        info!(
+            name_servers = %name_servers.join(", "),
+            config_source = ?self.config_source,
+            fallback = self.fallback.is_some(),
+            cache_size = opts.cache_size,
+            min_ttl_ms = opts.positive_min_ttl.map(|ttl| ttl.as_millis() as u64),
+            max_ttl_ms = opts.positive_max_ttl.map(|ttl| ttl.as_millis() as u64),
+            timeout_ms = opts.timeout.as_millis() as u64,
+            attempts = opts.attempts + 1,
+            rotate = opts.shuffle_dns_servers,
+            ip_strategy = ?self.ip_strategy,
+            use_hosts_file = opts.use_hosts_file,
+            validate_dnssec = self.validate_dnssec,
+            negative_ttl_ms = self.negative_ttl.as_millis() as u64,
+            circuit_threshold = self.circuit.as_ref().map(|c| c.threshold),
+            circuit_cooldown_ms = self.circuit.as_ref().map(|c| c.cooldown.as_millis() as u64),
+            serve_stale_ms = self.serve_stale.as_millis() as u64,
+            strict_fqdn = self.strict_fqdn,
+            timing_breakdown = self.timing_breakdown,
+            deny_private = self.deny.private,
+            deny_cidrs = ?self.deny.cidrs,
+            "DNS resolver configuration"
+        );
+    }
+
+    /// Returns the hosts whose answers are currently cached, sorted by
+    /// hostname.
+    ///
+    /// hickory doesn't expose its cache, so this is reconstructed from the
+    /// lookups made through this resolver and its clones. Expired answers are
+    /// left out, and entries hickory evicted early to stay within
+    /// [`cache_size`](HickoryDnsResolverBuilder::cache_size) may still show up.
+    pub fn cached_entries(&self) -> Vec<CacheEntry> {
+        let now = Instant::now();
+        let mut mirror = self.cache_mirror.write().unwrap();
+        // Expired answers are kept while they could still be served stale
+        mirror.retain(|_, entry| self.within_stale_window(entry, now));
+
+        let mut entries: Vec<CacheEntry> = mirror
+            .values()
+            .filter(|entry| entry.valid_until > now)
+            .cloned()
+            .collect();
+        entries.sort_by(|a, b| a.host.cmp(&b.host));
+        entries
+    }
+
+    /// Writes the currently cached answers to `path` as JSON, so a later
+    /// process can pick them up with [`load_cache`](Self::load_cache).
+    pub fn save_cache(&self, path: impl AsRef<Path>) -> Result<(), DnsCacheError> {
+        let entries: Vec<PersistedEntry> = self
+            .cached_entries()
+            .iter()
+            .map(PersistedEntry::from_entry)
+            .collect();
+        let json = serde_json::to_string_pretty(&entries).map_err(std::io::Error::from)?;
+        std::fs::write(path, json)?;
+        debug!("Saved {} cache entries", entries.len());
+        Ok(())
+    }
+
+    /// Reloads answers written by [`save_cache`](Self::save_cache), returning
+    /// how many were loaded.
+    ///
+    /// Entries that have expired since they were saved are dropped. The rest
+    /// are answered from memory, and counted as cache hits, until they
+    /// expire; then the host is looked up as usual.
+    pub fn load_cache(&self, path: impl AsRef<Path>) -> Result<usize, DnsCacheError> {
+        let json = std::fs::read_to_string(path)?;
+        let entries: Vec<PersistedEntry> = serde_json::from_str(&json).map_err(std::io::Error::from)?;
+
+        let mut preloaded = self.preloaded.write().unwrap();
+        for entry in entries.into_iter().filter_map(PersistedEntry::into_entry) {
+            self.counters.seed(&entry.host, entry.valid_until);
+            preloaded.insert(entry.host.clone(), entry);
+        }
+        debug!("Loaded {} cache entries", preloaded.len());
+        Ok(preloaded.len())
+    }
+
+    /// Returns a builder for tuning the cache and query options.
+    pub fn builder() -> HickoryDnsResolverBuilder {
+        HickoryDnsResolverBuilder::default()
+    }
+}
+
+impl Default for HickoryDnsResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builder for [`HickoryDnsResolver`].
+///
+/// Starts from the same defaults as [`HickoryDnsResolver::new`].
+#[derive(Clone)]
+pub struct HickoryDnsResolverBuilder {
+    upstream: Option<UpstreamDns>,
+    fallback_upstream: Option<UpstreamDns>,
+    protocol: DnsProtocol,
+    tls_server: Option<(Vec<IpAddr>, String)>,
+    name_servers: Vec<SocketAddr>,
+    bind_addr: Option<IpAddr>,
+    opts: ResolverOpts,
+    ip_strategy: IpStrategy,
+    max_concurrent_lookups: Option<usize>,
+    routable_only: bool,
+    shuffle_addresses: bool,
+    negative_ttl: Duration,
+    circuit_breaker: Option<(u32, Duration)>,
+    serve_stale: Duration,
+    on_resolve: Option<ResolveHook>,
+    timing_breakdown: bool,
+    event_capacity: usize,
+    strict_fqdn: bool,
+    deny: DenyList,
+    extra_hosts: StaticHosts,
+    search_domains: Vec<Name>,
+}
+
+impl Default for HickoryDnsResolverBuilder {
+    fn default() -> Self {
+        // Create custom resolver options with optimized caching
+        let mut opts = ResolverOpts::default();
+        opts.cache_size = 1024; // Increase cache size
+        opts.use_hosts_file = true;
+        opts.timeout = Duration::from_secs(3); // Reduce timeout from default
+        opts.attempts = 2; // Reduce retry attempts
+
+        HickoryDnsResolverBuilder {
+            upstream: None,
+            fallback_upstream: None,
+            protocol: DnsProtocol::default(),
+            tls_server: None,
+            name_servers: Vec::new(),
+            bind_addr: None,
+            opts,
+            ip_strategy: IpStrategy::default(),
+            max_concurrent_lookups: None,
+            routable_only: false,
+            shuffle_addresses: false,
+            negative_ttl: Duration::ZERO,
+            circuit_breaker: None,
+            serve_stale: Duration::ZERO,
+            on_resolve: None,
+            timing_breakdown: false,
+            event_capacity: DEFAULT_EVENT_CAPACITY,
+            strict_fqdn: false,
+            deny: DenyList::default(),
+            extra_hosts: StaticHosts::default(),
+            search_domains: Vec::new(),
+        }
+    }
+}
+
+impl HickoryDnsResolverBuilder {
+    /// Upstream name servers to send queries to.
+    pub fn upstream(mut self, upstream: UpstreamDns) -> Self {
+        self.upstream = Some(upstream);
+        self
+    }
+
+    /// Upstream name servers to try when the primary upstream fails, e.g.
+    /// because it timed out or is rate-limiting us.
+    ///
+    /// A host the primary reports as nonexistent isn't retried. Resolution
+    /// only fails if both upstreams do. The fallback uses the same
+    /// [`protocol`](Self::protocol) and options as the primary.
+    pub fn fallback_upstream(mut self, upstream: UpstreamDns) -> Self {
+        self.fallback_upstream = Some(upstream);
+        self
+    }
+
+    /// Transport for talking to the upstream name servers.
+    ///
+    /// Choosing [`DnsProtocol::Https`] without an explicit
+    /// [`upstream`](Self::upstream) sends queries to Cloudflare's
+    /// DNS-over-HTTPS endpoint.
+    pub fn protocol(mut self, protocol: DnsProtocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// DNS-over-TLS server to send queries to, for servers that aren't one of
+    /// the [`UpstreamDns`] presets.
+    ///
+    /// Queries go to port 853 on `ips`, and the server's certificate must be
+    /// valid for `server_name`, which is also sent as the TLS SNI. This
+    /// replaces any [`upstream`](Self::upstream) and switches the
+    /// [`protocol`](Self::protocol) to [`DnsProtocol::Tls`].
+    pub fn tls_server(mut self, ips: Vec<IpAddr>, server_name: impl Into<String>) -> Self {
+        self.tls_server = Some((ips, server_name.into()));
+        self.name_servers.clear();
+        self.protocol = DnsProtocol::Tls;
+        self
+    }
+
+    /// Plaintext name servers to send queries to, for servers that aren't
+    /// one of the [`UpstreamDns`] presets, such as a local resolver on a
+    /// non-standard port.
+    ///
+    /// This replaces any [`upstream`](Self::upstream) or
+    /// [`tls_server`](Self::tls_server); an empty list goes back to them.
+    /// Queries go over UDP, unless [`protocol`](Self::protocol) is then set
+    /// to [`DnsProtocol::Tcp`].
+    pub fn name_servers(mut self, addrs: Vec<SocketAddr>) -> Self {
+        self.name_servers = addrs;
+        self.tls_server = None;
+        self.protocol = DnsProtocol::Plaintext;
+        self
+    }
+
+    /// Local address DNS queries are sent from, for hosts with several
+    /// interfaces.
+    ///
+    /// Name servers of the other address family are skipped. Fails with
+    /// [`DnsCacheError::InvalidConfig`] if `ip` isn't an address of this
+    /// host.
+    pub fn bind_addr(mut self, ip: IpAddr) -> Result<Self, DnsCacheError> {
+        // Bind once up front so a bad address fails here, not on the first lookup
+        std::net::UdpSocket::bind(SocketAddr::new(ip, 0))
+            .map_err(|e| DnsCacheError::InvalidConfig(format!("can't bind DNS queries to {}: {}", ip, e)))?;
+        self.bind_addr = Some(ip);
+        Ok(self)
+    }
+
+    /// Maximum number of answers kept in the in-memory cache.
+    pub fn cache_size(mut self, cache_size: usize) -> Self {
+        self.opts.cache_size = cache_size;
+        self
+    }
+
+    /// Shortest time an answer is cached for, however low its TTL.
+    ///
+    /// Keeps a host that answers with TTL=1 from being looked up again every
+    /// second. If it exceeds [`max_ttl`](Self::max_ttl), it wins. Only
+    /// answers with addresses are affected, not failed lookups.
+    pub fn min_ttl(mut self, min_ttl: Duration) -> Self {
+        self.opts.positive_min_ttl = Some(min_ttl);
+        self
+    }
+
+    /// Longest time an answer is cached for, however high its TTL. Defaults
+    /// to hickory's cap of one day.
+    pub fn max_ttl(mut self, max_ttl: Duration) -> Self {
+        self.opts.positive_max_ttl = Some(max_ttl);
+        self
+    }
+
+    /// Timeout for a single query to an upstream name server.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.opts.timeout = timeout;
+        self
+    }
+
+    /// Number of times a query is sent before giving up (default 3), so 1
+    /// means no retries. Zero is treated as 1.
+    pub fn attempts(mut self, attempts: usize) -> Self {
+        // hickory's `attempts` counts retries after the first try
+        self.opts.attempts = attempts.saturating_sub(1);
+        self
+    }
+
+    /// Whether to spread queries across the upstream's name servers (default
+    /// `false`). By default hickory favours the fastest-responding ones.
+    ///
+    /// This sets hickory's `shuffle_dns_servers`, which picks name servers at
+    /// random for each query; its `rotate` option is not implemented.
+    pub fn rotate(mut self, rotate: bool) -> Self {
+        self.opts.shuffle_dns_servers = rotate;
+        self
+    }
+
+    /// Which address families to look up and return, and in what order.
+    ///
+    /// If no address of an allowed family is found, resolution fails rather
+    /// than returning an empty list.
+    pub fn ip_strategy(mut self, ip_strategy: IpStrategy) -> Self {
+        self.opts.ip_strategy = ip_strategy.lookup_strategy();
+        self.ip_strategy = ip_strategy;
+        self
+    }
+
+    /// Maximum number of lookups in flight at once across this resolver and
+    /// its clones; further lookups wait for one to finish.
+    ///
+    /// This keeps bursts of parallel requests from flooding the upstream.
+    /// Unlimited by default. A limit of 0 is treated as 1.
+    pub fn max_concurrent_lookups(mut self, max: usize) -> Self {
+        self.max_concurrent_lookups = Some(max.max(1));
+        self
+    }
+
+    /// Whether to validate answers with DNSSEC.
+    ///
+    /// Answers that fail validation, e.g. because they were tampered with or
+    /// their signatures are missing, are rejected with
+    /// [`DnsCacheError::DnssecValidation`] rather than
+    /// [`DnsCacheError::Resolve`]. Off by default.
+    pub fn validate_dnssec(mut self, validate: bool) -> Self {
+        self.opts.validate = validate;
+        self
+    }
+
+    /// Whether to drop addresses of a family this host has no route for,
+    /// e.g. AAAA answers on an IPv4-only network, so reqwest doesn't stall
+    /// trying to connect to them.
+    ///
+    /// Routes are probed once, when the resolver is built. If filtering
+    /// leaves no addresses, resolution fails with an error naming the
+    /// constraints. Off by default.
+    pub fn routable_only(mut self, routable_only: bool) -> Self {
+        self.routable_only = routable_only;
+        self
+    }
+
+    /// Whether to return addresses in random order on every resolution.
+    ///
+    /// hickory returns a host's addresses in a fixed order, so reqwest always
+    /// connects to the first one. Shuffling spreads connections across all of
+    /// a host's A/AAAA records. The [IP strategy](Self::ip_strategy)'s family
+    /// preference still holds. Off by default.
+    pub fn shuffle_addresses(mut self, shuffle_addresses: bool) -> Self {
+        self.shuffle_addresses = shuffle_addresses;
+        self
+    }
+
+    /// How long to remember that a host failed to resolve.
+    ///
+    /// Within this window, lookups of the same host fail immediately instead
+    /// of querying upstream again. Zero, the default, disables this.
+    pub fn negative_ttl(mut self, negative_ttl: Duration) -> Self {
+        self.negative_ttl = negative_ttl;
+        self
+    }
+
+    /// Trips a per-host circuit breaker after `threshold` consecutive failed
+    /// lookups of a host, each within `cooldown` of the last.
+    ///
+    /// While the circuit is open, lookups of that host fail immediately
+    /// without querying upstream, so a zone that's down doesn't add a full
+    /// timeout to every request for it; other hosts are unaffected. After
+    /// `cooldown`, one probe lookup is let through: if it succeeds the
+    /// circuit closes, otherwise it stays open for another `cooldown`. A
+    /// host upstream reports as nonexistent counts as a success, since
+    /// upstream answered. A `threshold` of zero disables the breaker, as
+    /// does not calling this.
+    pub fn circuit_breaker(mut self, threshold: u32, cooldown: Duration) -> Self {
+        self.circuit_breaker = Some((threshold, cooldown));
+        self
+    }
+
+    /// How long after an answer expires it may still be served if a fresh
+    /// lookup of the host fails.
+    ///
+    /// This keeps requests working through a brief DNS outage, at the cost
+    /// of possibly connecting to addresses that have since changed. Each
+    /// stale answer is logged as a warning. A host that upstream says doesn't
+    /// exist still fails. Zero, the default, disables this.
+    pub fn serve_stale(mut self, serve_stale: Duration) -> Self {
+        self.serve_stale = serve_stale;
+        self
+    }
+
+    /// Address ranges that resolved addresses must not fall into, e.g. to
+    /// keep user-supplied URLs away from internal networks (SSRF).
+    ///
+    /// Addresses in these ranges are dropped from every answer. If none are
+    /// left, resolution fails with [`DnsCacheError::Blocked`]. Calling this
+    /// again replaces the previous list.
+    ///
+    /// This only screens what the resolver resolves, and reqwest never asks
+    /// it about a URL whose host is an address literal such as
+    /// `http://169.254.169.254/`. Check such URLs with
+    /// [`HickoryDnsResolver::check_url`] before sending, and give the client
+    /// [`HickoryDnsResolver::redirect_policy`] so redirects to them are
+    /// refused too.
+    pub fn deny_cidrs(mut self, cidrs: Vec<IpNet>) -> Self {
+        self.deny.cidrs = cidrs;
+        self
+    }
+
+    /// Denies, as [`deny_cidrs`](Self::deny_cidrs) does, addresses that only
+    /// make sense inside a host or network: loopback, unspecified,
+    /// link-local (`169.254.0.0/16`, `fe80::/10`), RFC 1918 private ranges
+    /// and IPv6 unique local addresses (`fc00::/7`). IPv4-mapped IPv6
+    /// addresses are judged by the IPv4 address they map to. Off by default.
+    ///
+    /// Like [`deny_cidrs`](Self::deny_cidrs), this doesn't cover URLs with an
+    /// address literal host unless they're checked separately.
+    pub fn deny_private(mut self, deny_private: bool) -> Self {
+        self.deny.private = deny_private;
+        self
+    }
+
+    /// Rejects names that aren't fully qualified, such as `localhost` or
+    /// `db`, with [`DnsCacheError::NotFullyQualified`] before any lookup.
+    ///
+    /// A name passes if it has at least two labels and ends in something
+    /// shaped like a TLD; IP addresses always pass. Names are checked as
+    /// given, so this also rejects short names meant for
+    /// [`search_domains`](Self::search_domains) and the hosts files. Off by
+    /// default.
+    pub fn strict_fqdn(mut self, strict_fqdn: bool) -> Self {
+        self.strict_fqdn = strict_fqdn;
+        self
+    }
+
+    /// Registers a function called with the host and final addresses of
+    /// every successful [`resolve_host`](HickoryDnsResolver::resolve_host),
+    /// [`resolve_host_with_ttl`](HickoryDnsResolver::resolve_host_with_ttl)
+    /// and [`resolve_host_fresh`](HickoryDnsResolver::resolve_host_fresh),
+    /// which includes every lookup reqwest makes through this resolver.
+    ///
+    /// Use it for audit logging or to check addresses against an allowlist.
+    /// It runs on the resolving task before the addresses are returned, so
+    /// it should be quick. Calling this again replaces the previous hook.
+    pub fn on_resolve(mut self, callback: impl Fn(&str, &[IpAddr]) + Send + Sync + 'static) -> Self {
+        self.on_resolve = Some(Arc::new(callback));
+        self
+    }
+
+    /// Whether each lookup's log event breaks its `duration_ms` down into
+    /// `queue_ms`, waiting for a
+    /// [`max_concurrent_lookups`](Self::max_concurrent_lookups) slot;
+    /// `query_ms`, the hickory lookup itself; and `local_ms`, the resolver's
+    /// own processing. Off by default.
+    ///
+    /// hickory doesn't report when a query was sent or its answer arrived,
+    /// so `query_ms` is timed around the whole hickory call: network round
+    /// trips, retries and hickory's own processing, or just a cache read on
+    /// a hit. That still separates upstream time from time spent locally.
+    pub fn timing_breakdown(mut self, enabled: bool) -> Self {
+        self.timing_breakdown = enabled;
+        self
+    }
+
+    /// How many events a [`subscribe`](HickoryDnsResolver::subscribe)
+    /// receiver can fall behind by before it misses the oldest ones; 1024 by
+    /// default.
+    pub fn event_capacity(mut self, capacity: usize) -> Self {
+        self.event_capacity = capacity;
+        self
+    }
+
+    /// Whether to consult the OS hosts file before querying upstream.
+    ///
+    /// On by default. Turn it off where the hosts file has stale entries
+    /// that would shadow real DNS, as in some containers. `localhost` still
+    /// resolves without it.
+    pub fn use_hosts_file(mut self, use_hosts_file: bool) -> Self {
+        self.opts.use_hosts_file = use_hosts_file;
+        self
+    }
+
+    /// Reads additional static host mappings from a file in `/etc/hosts`
+    /// format.
+    ///
+    /// Hosts listed there resolve to the given addresses without querying
+    /// hickory; any other host is resolved as usual. Calling this again merges
+    /// in another file. Fails with [`DnsCacheError::Io`] if the file can't be
+    /// read, or [`DnsCacheError::InvalidConfig`] if it isn't valid hosts
+    /// syntax.
+    pub fn extra_hosts(mut self, path: impl AsRef<Path>) -> Result<Self, DnsCacheError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        self.extra_hosts
+            .merge(&contents)
+            .map_err(|e| DnsCacheError::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+        Ok(self)
+    }
+
+    /// Domains appended, in order, to names that have fewer labels than
+    /// [`ndots`](Self::ndots), like `search` in `resolv.conf`.
+    ///
+    /// With the default `ndots` of 1, `myservice` is tried as
+    /// `myservice.<domain>` for each domain before being looked up as-is.
+    /// Domains from the system configuration, if used, are tried first.
+    /// Fails with [`DnsCacheError::InvalidConfig`] if a domain isn't a valid
+    /// DNS name.
+    pub fn search_domains(mut self, domains: Vec<String>) -> Result<Self, DnsCacheError> {
+        for domain in domains {
+            let name = Name::from_str(&domain).map_err(|e| {
+                DnsCacheError::InvalidConfig(format!("invalid search domain `{}`: {}", domain, e))
+            })?;
+            self.search_domains.push(name);
+        }
+        Ok(self)
+    }
+
+    /// Minimum number of labels a name must have to be looked up as-is
+    /// before trying the [search domains](Self::search_domains). Defaults
+    /// to 1.
+    pub fn ndots(mut self, ndots: usize) -> Self {
+        self.opts.ndots = ndots;
+        self
+    }
+
+    /// Builds the resolver.
+    ///
+    /// Options are checked here, so a configuration that can't work fails
+    /// with [`DnsCacheError::InvalidConfig`] now rather than confusingly at
+    /// the first lookup: a [`tls_server`](Self::tls_server) without an
+    /// address or server name, a [`protocol`](Self::protocol) that doesn't
+    /// fit the [`tls_server`](Self::tls_server) or
+    /// [`name_servers`](Self::name_servers) given, or a zero
+    /// [`timeout`](Self::timeout).
+    pub fn build(self) -> Result<HickoryDnsResolver, DnsCacheError> {
+        self.validate()?;
+
+        // Applied to the fallback upstream's config as well as the primary's
+        let customize = |mut config: ResolverConfig| {
+            for domain in &self.search_domains {
+                config.add_search(domain.clone());
+            }
+            match self.bind_addr {
+                Some(ip) => upstream::bind_config(config, ip),
+                None => config,
+            }
+        };
+        let (config, config_source) = match &self.tls_server {
+            Some((ips, server_name)) => (upstream::tls_config(ips, server_name), ConfigSource::Custom),
+            None if !self.name_servers.is_empty() => (
+                upstream::custom_config(&self.name_servers, self.protocol),
+                ConfigSource::Custom,
+            ),
+            None => upstream::resolver_config(self.upstream, self.protocol),
+        };
+        let config = customize(config);
+        let resolver = TokioAsyncResolver::tokio(config.clone(), self.opts.clone());
+        let validate_dnssec = self.opts.validate;
+        let fallback = self.fallback_upstream.map(|fallback| {
+            let (config, _) = upstream::resolver_config(Some(fallback), self.protocol);
+            let config = customize(config);
+            TokioAsyncResolver::tokio(config, self.opts.clone())
+        });
+
+        Ok(HickoryDnsResolver {
+            resolver,
+            fallback,
+            config: Arc::new((config, self.opts)),
+            config_source,
+            counters: Arc::new(CacheCounters::default()),
+            lookup_durations: Arc::new(LookupDurations::default()),
+            preloaded: Arc::new(RwLock::new(HashMap::new())),
+            cache_mirror: Arc::new(RwLock::new(HashMap::new())),
+            ip_strategy: self.ip_strategy,
+            routable: self.routable_only.then(RoutableFamilies::probe),
+            shuffle_addresses: self.shuffle_addresses,
+            validate_dnssec,
+            lookup_permits: self.max_concurrent_lookups.map(|max| Arc::new(Semaphore::new(max))),
+            negative_ttl: self.negative_ttl,
+            failures: Arc::new(Mutex::new(HashMap::new())),
+            circuit: self
+                .circuit_breaker
+                .filter(|&(threshold, _)| threshold > 0)
+                .map(|(threshold, cooldown)| Arc::new(CircuitBreaker::new(threshold, cooldown))),
+            serve_stale: self.serve_stale,
+            on_resolve: self.on_resolve,
+            timing_breakdown: self.timing_breakdown,
+            events: broadcast::channel(self.event_capacity.max(1)).0,
+            strict_fqdn: self.strict_fqdn,
+            deny: Arc::new(self.deny),
+            extra_hosts: Arc::new(self.extra_hosts),
+        })
+    }
+
+    // Rejects combinations of options that would otherwise be silently
+    // ignored or only fail once a lookup is attempted
+    fn validate(&self) -> Result<(), DnsCacheError> {
+        let invalid = |message: String| Err(DnsCacheError::InvalidConfig(message));
+        if let Some((ips, server_name)) = &self.tls_server {
+            if ips.is_empty() {
+                return invalid("tls_server needs at least one address".to_string());
+            }
+            if server_name.is_empty() {
+                return invalid("tls_server needs a server name to validate the certificate against".to_string());
+            }
+            if self.protocol != DnsProtocol::Tls {
+                return invalid(format!("tls_server is queried over DNS-over-TLS, not {:?}", self.protocol));
+            }
+        }
+        if !self.name_servers.is_empty() && !matches!(self.protocol, DnsProtocol::Plaintext | DnsProtocol::Tcp) {
+            return invalid(format!(
+                "name_servers are queried over UDP or TCP, not {:?}; use tls_server for DNS-over-TLS",
+                self.protocol
+            ));
+        }
+        if self.opts.timeout.is_zero() {
+            return invalid("timeout must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Logs a lookup that was dropped before it finished, e.g. because the
+/// caller's timeout fired. Disarmed once the lookup completes.
+struct CancelGuard<'a> {
+    host: &'a str,
+    start: Instant,
+    events: &'a broadcast::Sender<ResolveEvent>,
+    armed: bool,
+}
+
+impl Drop for CancelGuard<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            info!(
+                host = self.host,
+                duration_ms = millis(self.start.elapsed()),
+                outcome = "cancelled",
+                "DNS resolution cancelled"
+            );
+            events::emit(self.events, self.host, self.start.elapsed(), ResolveOutcome::Cancelled, 0);
+        }
+    }
+}
+
+/// Where the time of one lookup went, for the
+/// [`timing_breakdown`](HickoryDnsResolverBuilder::timing_breakdown) fields.
+#[derive(Clone, Copy, Debug, Default)]
+struct QueryTiming {
+    /// Waiting for a `max_concurrent_lookups` slot.
+    queued: Duration,
+    /// The hickory lookup, end to end: hickory doesn't say when the query
+    /// went out or the answer came back, so this is timed around it.
+    query: Duration,
+}
+
+impl QueryTiming {
+    // What's left of the lookup's `total` time: our own processing
+    fn local(&self, total: Duration) -> Duration {
+        total.saturating_sub(self.queued + self.query)
+    }
+}
+
+/// A hook registered with [`HickoryDnsResolverBuilder::on_resolve`].
+type ResolveHook = Arc<dyn Fn(&str, &[IpAddr]) + Send + Sync>;
+
+// Durations are logged as fractional milliseconds so they stay queryable as
+// numbers in structured logs
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// The punycode form of an internationalized `host`, which is what DNS and the
+// cache know it by. ASCII hosts are passed through untouched, not even
+// lowercased.
+fn to_ascii(host: &str) -> Result<Cow<'_, str>, DnsCacheError> {
+    if host.is_ascii() {
+        return Ok(Cow::Borrowed(host));
+    }
+    let ascii = idna::domain_to_ascii(host).map_err(|_| DnsCacheError::InvalidHostname {
+        host: host.to_string(),
+    })?;
+    debug!(host, ascii = %ascii, "Converted internationalized hostname to punycode");
+    Ok(Cow::Owned(ascii))
+}
+
+// Whether `host` is an IP address or a fully-qualified name: at least two
+// non-empty labels, the last of which looks like a TLD (letters only, or
+// punycode). There's no list of real TLDs to check against, so this catches
+// relative names like `localhost` or `db.internal1`, not made-up TLDs.
+fn is_fqdn(host: &str) -> bool {
+    if host.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    let name = host.strip_suffix('.').unwrap_or(host);
+    let labels: Vec<&str> = name.split('.').collect();
+    let tld = labels[labels.len() - 1];
+    labels.len() >= 2
+        && labels.iter().all(|label| !label.is_empty())
+        && (tld.starts_with("xn--") || (tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic())))
+}
+
+// Custom trait implementation for reqwest DNS resolution
+impl reqwest::dns::Resolve for HickoryDnsResolver {
+    /// Resolves `name` to a list of socket addresses with port 0; reqwest
+    /// fills in the port from the request URL.
+    ///
+    /// The lookup goes through hickory's cache, so a repeated call for a host
+    /// whose answer is still within its TTL returns without a network query.
+    ///
+    /// Behind a proxy set with `ClientBuilder::proxy`, reqwest only calls this
+    /// for the proxy's host. The target host is handed to the proxy by name,
+    /// in the request line for plain HTTP or a `CONNECT` for HTTPS, and the
+    /// proxy resolves it itself, so it never reaches this cache.
+    ///
+    /// Nothing is spawned: the lookup runs inside the returned future, so
+    /// when reqwest drops it, e.g. because the request timed out, the query
+    /// is abandoned and its retries never sent.
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let this = self.clone();
+        let host = name.as_str().to_string();
+        // A child of the caller's span, e.g. the request being connected
+        let span = info_span!("resolve", host = %host);
+
+        Box::pin(
+            async move {
+                match this.resolve_host_port(&host, 0).await {
+                    Ok(addrs) => {
+                        Ok(Box::new(addrs.into_iter()) as Box<dyn Iterator<Item = SocketAddr> + Send>)
+                    },
+                    Err(e) => Err(Box::new(e) as Box<dyn Error + Send + Sync>),
+                }
+            }
+            .instrument(span),
+        )
+    }
+}
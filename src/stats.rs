@@ -0,0 +1,167 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Snapshot of how many lookups were answered from the cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups answered from hickory's in-memory cache.
+    pub hits: u64,
+    /// Lookups that had to go to the upstream name servers.
+    pub misses: u64,
+    /// Lookups upstream couldn't answer, including any then served stale.
+    pub failures: u64,
+}
+
+impl CacheStats {
+    /// The fraction of successful lookups answered from the cache, or `None`
+    /// if there haven't been any.
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let answered = self.hits + self.misses;
+        (answered > 0).then(|| self.hits as f64 / answered as f64)
+    }
+}
+
+/// A host whose answer is currently cached, as returned by
+/// [`HickoryDnsResolver::cached_entries`](crate::HickoryDnsResolver::cached_entries).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CacheEntry {
+    /// The hostname as it was looked up.
+    pub host: String,
+    /// Every address in the cached answer, before any IP strategy filtering.
+    pub addrs: Vec<IpAddr>,
+    /// When the cached answer expires.
+    pub valid_until: Instant,
+}
+
+impl CacheEntry {
+    /// Time left until the answer expires; zero once it has.
+    pub fn ttl_remaining(&self) -> Duration {
+        self.valid_until.saturating_duration_since(Instant::now())
+    }
+}
+
+/// Percentiles of the lookup durations a resolver has observed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LatencySummary {
+    /// Number of lookups the percentiles are computed over.
+    pub count: usize,
+    /// Mean lookup duration.
+    pub mean: Duration,
+    /// Median lookup duration.
+    pub p50: Duration,
+    /// 90th percentile lookup duration.
+    pub p90: Duration,
+    /// 99th percentile lookup duration.
+    pub p99: Duration,
+    /// Slowest lookup.
+    pub max: Duration,
+}
+
+impl LatencySummary {
+    /// Summarizes `durations` using nearest-rank percentiles, or returns
+    /// `None` if there are none.
+    pub fn from_durations(durations: &[Duration]) -> Option<Self> {
+        if durations.is_empty() {
+            return None;
+        }
+
+        let mut sorted = durations.to_vec();
+        sorted.sort_unstable();
+        let percentile = |p: usize| sorted[(sorted.len() * p).div_ceil(100).max(1) - 1];
+
+        Some(LatencySummary {
+            count: sorted.len(),
+            mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// How many of the latest lookup durations
+/// [`HickoryDnsResolver::latency_summary`](crate::HickoryDnsResolver::latency_summary)
+/// covers, so a resolver that runs indefinitely doesn't grow without bound.
+pub const LATENCY_WINDOW: usize = 10_000;
+
+/// The durations of the latest [`LATENCY_WINDOW`] successful lookups.
+#[derive(Debug, Default)]
+pub(crate) struct LookupDurations {
+    durations: Mutex<VecDeque<Duration>>,
+}
+
+impl LookupDurations {
+    /// Records a lookup, forgetting the oldest one once the window is full.
+    pub(crate) fn record(&self, duration: Duration) {
+        let mut durations = self.durations.lock().unwrap();
+        if durations.len() == LATENCY_WINDOW {
+            durations.pop_front();
+        }
+        durations.push_back(duration);
+    }
+
+    pub(crate) fn summary(&self) -> Option<LatencySummary> {
+        LatencySummary::from_durations(self.durations.lock().unwrap().make_contiguous())
+    }
+}
+
+/// Shared hit/miss counters behind [`CacheStats`].
+///
+/// hickory doesn't report whether an answer came from its cache, but a cached
+/// answer keeps the `valid_until` deadline it was stored with, while a fresh
+/// one gets a new deadline. Remembering the last deadline seen per host is
+/// enough to tell the two apart.
+#[derive(Debug, Default)]
+pub(crate) struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    failures: AtomicU64,
+    deadlines: Mutex<HashMap<String, Instant>>,
+}
+
+impl CacheCounters {
+    /// Records a successful lookup of `host` and returns whether it was a
+    /// cache hit.
+    pub(crate) fn record(&self, host: &str, valid_until: Instant) -> bool {
+        let previous = self
+            .deadlines
+            .lock()
+            .unwrap()
+            .insert(host.to_string(), valid_until);
+        let hit = previous == Some(valid_until);
+
+        if hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        hit
+    }
+
+    /// Records a lookup that failed upstream.
+    pub(crate) fn record_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Remembers `valid_until` as the deadline already seen for `host`
+    /// without counting a lookup, so the next answer with that deadline
+    /// counts as a hit.
+    pub(crate) fn seed(&self, host: &str, valid_until: Instant) {
+        self.deadlines
+            .lock()
+            .unwrap()
+            .insert(host.to_string(), valid_until);
+    }
+
+    pub(crate) fn snapshot(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Deterministic resolvers for tests, enabled by the `test-util` feature.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::lookup_times::record_lookup_time;
+
+/// A `reqwest::dns::Resolve` implementation that answers from a fixed map of
+/// hostnames to addresses, without any network access.
+///
+/// Unknown hosts fail with `NotFound`, like a real lookup would. Answered
+/// hosts are recorded for [`with_lookup_times`](crate::with_lookup_times)
+/// as taking no time, so reuse detection works as with a real resolver.
+#[derive(Clone, Debug, Default)]
+pub struct StaticResolver {
+    hosts: Arc<HashMap<String, Vec<IpAddr>>>,
+}
+
+impl StaticResolver {
+    /// Creates a resolver that answers from `hosts`.
+    pub fn new(hosts: HashMap<String, Vec<IpAddr>>) -> Self {
+        StaticResolver {
+            hosts: Arc::new(hosts),
+        }
+    }
+
+    /// Adds a mapping for `host`, replacing any existing one.
+    pub fn with_host(mut self, host: impl Into<String>, ips: Vec<IpAddr>) -> Self {
+        Arc::make_mut(&mut self.hosts).insert(host.into(), ips);
+        self
+    }
+}
+
+impl reqwest::dns::Resolve for StaticResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let result = match self.hosts.get(name.as_str()) {
+            Some(ips) => {
+                record_lookup_time(name.as_str(), Duration::ZERO);
+                let addrs: Vec<SocketAddr> = ips.iter().map(|&ip| SocketAddr::new(ip, 0)).collect();
+                Ok(Box::new(addrs.into_iter()) as Box<dyn Iterator<Item = SocketAddr> + Send>)
+            }
+            None => Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no static mapping for {}", name.as_str()),
+            )) as Box<dyn Error + Send + Sync>),
+        };
+
+        Box::pin(std::future::ready(result))
+    }
+}
@@ -0,0 +1,170 @@
+use std::net::{IpAddr, SocketAddr};
+
+use hickory_resolver::config::{NameServerConfigGroup, Protocol, ResolverConfig};
+use hickory_resolver::system_conf::read_system_conf;
+use tracing::warn;
+
+/// Upstream name servers the resolver sends its queries to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpstreamDns {
+    /// Name servers from the OS configuration (`/etc/resolv.conf` on Unix,
+    /// the registry on Windows).
+    System,
+    /// Cloudflare public DNS (`1.1.1.1`, `1.0.0.1`).
+    Cloudflare,
+    /// Google public DNS (`8.8.8.8`, `8.8.4.4`). This is hickory's default.
+    Google,
+    /// Quad9 public DNS (`9.9.9.9`, `149.112.112.112`).
+    Quad9,
+}
+
+/// Transport used to talk to the upstream name servers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DnsProtocol {
+    /// Unencrypted DNS over UDP, retrying over TCP for truncated answers.
+    #[default]
+    Plaintext,
+    /// Unencrypted DNS over TCP only.
+    Tcp,
+    /// DNS-over-HTTPS (RFC 8484) on port 443.
+    Https,
+    /// DNS-over-TLS (RFC 7858) on port 853.
+    Tls,
+}
+
+/// Where a resolver's name servers came from, as reported by
+/// [`HickoryDnsResolver::config_source`](crate::HickoryDnsResolver::config_source).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// The OS configuration, as asked for with [`UpstreamDns::System`].
+    System,
+    /// [`UpstreamDns::System`] was asked for but the OS configuration
+    /// couldn't be read, so queries go to hickory's default (Google public
+    /// DNS) instead.
+    SystemFallback,
+    /// One of the public [`UpstreamDns`] presets. Without an explicit
+    /// upstream this is hickory's default, Google public DNS, not the OS
+    /// configuration.
+    Preset,
+    /// Name servers given explicitly, with `name_servers` or `tls_server`.
+    Custom,
+}
+
+/// Port DNS-over-TLS servers listen on.
+pub(crate) const DOT_PORT: u16 = 853;
+
+/// Builds the hickory `ResolverConfig` for an upstream and protocol.
+///
+/// With no explicit upstream, plaintext uses hickory's default (Google) and
+/// the encrypted protocols use Cloudflare.
+pub(crate) fn resolver_config(upstream: Option<UpstreamDns>, protocol: DnsProtocol) -> (ResolverConfig, ConfigSource) {
+    let config = match (upstream, protocol) {
+        (_, DnsProtocol::Tcp) => {
+            let (config, source) = resolver_config(upstream, DnsProtocol::Plaintext);
+            return (tcp_only(config), source);
+        }
+        (None, DnsProtocol::Plaintext) => ResolverConfig::default(),
+        (Some(upstream), DnsProtocol::Plaintext) => return upstream.plaintext_config(),
+        (None, DnsProtocol::Https) => ResolverConfig::cloudflare_https(),
+        (Some(UpstreamDns::System), DnsProtocol::Https) => {
+            warn!("System name servers don't support DNS-over-HTTPS, using Cloudflare");
+            ResolverConfig::cloudflare_https()
+        }
+        (Some(UpstreamDns::Cloudflare), DnsProtocol::Https) => ResolverConfig::cloudflare_https(),
+        (Some(UpstreamDns::Google), DnsProtocol::Https) => ResolverConfig::google_https(),
+        (Some(UpstreamDns::Quad9), DnsProtocol::Https) => ResolverConfig::quad9_https(),
+        (None, DnsProtocol::Tls) => ResolverConfig::cloudflare_tls(),
+        (Some(UpstreamDns::System), DnsProtocol::Tls) => {
+            warn!("System name servers don't support DNS-over-TLS, using Cloudflare");
+            ResolverConfig::cloudflare_tls()
+        }
+        (Some(UpstreamDns::Cloudflare), DnsProtocol::Tls) => ResolverConfig::cloudflare_tls(),
+        (Some(UpstreamDns::Google), DnsProtocol::Tls) => ResolverConfig::google_tls(),
+        (Some(UpstreamDns::Quad9), DnsProtocol::Tls) => ResolverConfig::quad9_tls(),
+    };
+    (config, ConfigSource::Preset)
+}
+
+/// Builds a `ResolverConfig` for a DNS-over-TLS server that isn't one of the
+/// presets, verifying its certificate against `server_name`.
+pub(crate) fn tls_config(ips: &[IpAddr], server_name: &str) -> ResolverConfig {
+    ResolverConfig::from_parts(
+        None,
+        vec![],
+        NameServerConfigGroup::from_ips_tls(ips, DOT_PORT, server_name.to_string(), true),
+    )
+}
+
+/// Builds a plaintext `ResolverConfig` for name servers at arbitrary
+/// addresses, each queried over UDP and over TCP for truncated answers, or
+/// only over TCP with [`DnsProtocol::Tcp`].
+pub(crate) fn custom_config(addrs: &[SocketAddr], protocol: DnsProtocol) -> ResolverConfig {
+    let mut name_servers = NameServerConfigGroup::new();
+    for addr in addrs {
+        name_servers.merge(NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true));
+    }
+    let config = ResolverConfig::from_parts(None, vec![], name_servers);
+    match protocol {
+        DnsProtocol::Tcp => tcp_only(config),
+        _ => config,
+    }
+}
+
+/// Drops the UDP name servers from a plaintext `config`, keeping their TCP
+/// counterparts.
+fn tcp_only(config: ResolverConfig) -> ResolverConfig {
+    let name_servers: Vec<_> = config
+        .name_servers()
+        .iter()
+        .filter(|ns| ns.protocol == Protocol::Tcp)
+        .cloned()
+        .collect();
+    ResolverConfig::from_parts(config.domain().cloned(), config.search().to_vec(), name_servers)
+}
+
+/// Makes queries in `config` originate from `bind_ip`.
+///
+/// Name servers of the other address family can't be reached from that
+/// address, so they're dropped, unless that would leave none.
+pub(crate) fn bind_config(config: ResolverConfig, bind_ip: IpAddr) -> ResolverConfig {
+    let bind_addr = SocketAddr::new(bind_ip, 0);
+    let mut name_servers: Vec<_> = config
+        .name_servers()
+        .iter()
+        .filter(|ns| ns.socket_addr.is_ipv4() == bind_ip.is_ipv4())
+        .cloned()
+        .collect();
+    if name_servers.is_empty() {
+        warn!("No name servers share the address family of {}, queries will likely fail", bind_ip);
+        name_servers = config.name_servers().to_vec();
+    }
+    for ns in &mut name_servers {
+        ns.bind_addr = Some(bind_addr);
+    }
+
+    ResolverConfig::from_parts(config.domain().cloned(), config.search().to_vec(), name_servers)
+}
+
+impl UpstreamDns {
+    /// Maps the preset onto the corresponding plaintext hickory
+    /// `ResolverConfig`.
+    ///
+    /// If the system configuration can't be read, this falls back to
+    /// hickory's default (Google) config, logs a warning and says so in the
+    /// returned [`ConfigSource`].
+    fn plaintext_config(self) -> (ResolverConfig, ConfigSource) {
+        let config = match self {
+            UpstreamDns::System => match read_system_conf() {
+                Ok((config, _opts)) => return (config, ConfigSource::System),
+                Err(e) => {
+                    warn!("Failed to read system DNS config, falling back to Google public DNS: {}", e);
+                    return (ResolverConfig::default(), ConfigSource::SystemFallback);
+                }
+            },
+            UpstreamDns::Cloudflare => ResolverConfig::cloudflare(),
+            UpstreamDns::Google => ResolverConfig::google(),
+            UpstreamDns::Quad9 => ResolverConfig::quad9(),
+        };
+        (config, ConfigSource::Preset)
+    }
+}
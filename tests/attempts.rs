@@ -0,0 +1,13 @@
+use std::sync::atomic::Ordering;
+mod common;
+use common::{resolver_for, silent_name_server};
+
+#[tokio::test]
+async fn single_attempt_does_not_retry() {
+    let (addr, queries) = silent_name_server().await;
+    // One record type, so a single query per attempt
+    let resolver = resolver_for(addr).build().unwrap();
+
+    assert!(resolver.resolve_host("example.com").await.is_err());
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+}
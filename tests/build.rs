@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+use dns_query_caching::{DnsCacheError, DnsProtocol, HickoryDnsResolver};
+
+fn assert_invalid(result: Result<HickoryDnsResolver, DnsCacheError>) {
+    assert!(matches!(result, Err(DnsCacheError::InvalidConfig(_))), "{:?}", result.err());
+}
+
+#[test]
+fn contradictory_options_fail_at_build() {
+    assert_invalid(
+        HickoryDnsResolver::builder()
+            .name_servers(vec!["127.0.0.1:53".parse().unwrap()])
+            .protocol(DnsProtocol::Tls)
+            .build(),
+    );
+    assert_invalid(
+        HickoryDnsResolver::builder()
+            .tls_server(vec!["1.1.1.1".parse().unwrap()], "one.one.one.one")
+            .protocol(DnsProtocol::Https)
+            .build(),
+    );
+    assert_invalid(HickoryDnsResolver::builder().tls_server(vec![], "one.one.one.one").build());
+    assert_invalid(HickoryDnsResolver::builder().timeout(Duration::ZERO).build());
+}
+
+#[test]
+fn the_defaults_build() {
+    assert!(HickoryDnsResolver::builder().build().is_ok());
+}
@@ -0,0 +1,61 @@
+#![cfg(unix)]
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use dns_query_caching::cache_server::{self, CacheServerClient};
+use dns_query_caching::HickoryDnsResolver;
+
+mod common;
+use common::{resolver_for, serve_dns, silent_name_server, DNS_ANSWER};
+
+#[tokio::test]
+async fn clients_share_the_servers_cache_and_fall_back_without_it() {
+    let (addr, _up) = serve_dns(60).await;
+    let shared = resolver_for(addr).build().unwrap();
+    let path = std::env::temp_dir().join(format!("cache-server-{}.sock", std::process::id()));
+    tokio::spawn(cache_server::serve(shared.clone(), path.clone()));
+    while !path.exists() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // The clients' own resolver can't reach any upstream, so answers for
+    // example.com can only come from the server
+    let (silent, _) = silent_name_server().await;
+    let local = resolver_for(silent)
+        .use_hosts_file(true)
+        .timeout(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    let first = CacheServerClient::new(&path, local.clone());
+    let second = CacheServerClient::new(&path, local.clone());
+    assert_eq!(first.resolve_host("example.com").await.unwrap(), [IpAddr::V4(DNS_ANSWER)]);
+    assert_eq!(second.resolve_host("example.com").await.unwrap(), [IpAddr::V4(DNS_ANSWER)]);
+    assert_eq!((shared.stats().misses, shared.stats().hits), (1, 1));
+
+    // Without the server, lookups are answered locally instead
+    std::fs::remove_file(&path).unwrap();
+    assert!(first.resolve_host("localhost").await.is_ok());
+    assert!(first.resolve_host("example.com").await.is_err());
+}
+
+#[tokio::test]
+async fn a_server_that_never_answers_falls_back_after_the_timeout() {
+    // Accepts connections but never replies
+    let path = std::env::temp_dir().join(format!("cache-server-silent-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            held.push(stream);
+        }
+    });
+
+    let local = HickoryDnsResolver::builder().build().unwrap();
+    let client = CacheServerClient::with_timeout(&path, local, Duration::from_millis(100));
+    let start = Instant::now();
+    assert!(client.resolve_host("localhost").await.is_ok());
+    assert!(start.elapsed() < Duration::from_secs(1), "{:?}", start.elapsed());
+    std::fs::remove_file(&path).unwrap();
+}
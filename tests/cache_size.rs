@@ -0,0 +1,50 @@
+mod common;
+use common::{resolver_for, serve_dns};
+
+#[tokio::test]
+async fn the_least_recently_used_answer_is_evicted_at_capacity() {
+    let (addr, _up) = serve_dns(60).await;
+    // IPv4 only, so each host takes exactly one cache entry
+    let resolver = resolver_for(addr).cache_size(4).build().unwrap();
+    assert_eq!(resolver.cache_size(), 4);
+
+    for i in 1..=5 {
+        resolver.resolve_host(&format!("host{}.example.com", i)).await.unwrap();
+    }
+    assert_eq!(resolver.stats().misses, 5);
+
+    // The latest host is still cached, the first was pushed out
+    resolver.resolve_host("host5.example.com").await.unwrap();
+    assert_eq!(resolver.stats().hits, 1);
+    resolver.resolve_host("host1.example.com").await.unwrap();
+    assert_eq!(resolver.stats().misses, 6);
+}
+
+#[tokio::test]
+async fn the_cache_pressure_command_sees_the_earliest_hosts_evicted() {
+    let (addr, _up) = serve_dns(60).await;
+    let args = [
+        "--log-stdout",
+        "--dns-server",
+        &addr.to_string(),
+        "--dns-cache-size",
+        "4",
+        "cache-pressure",
+        "--extra",
+        "2",
+        "--template",
+        "host{}.example.com",
+    ]
+    .map(String::from);
+    // The name server runs on this runtime, so wait for the CLI off of it
+    let output = tokio::task::spawn_blocking(move || {
+        std::process::Command::new(env!("CARGO_BIN_EXE_dns_query_caching")).args(args).output().unwrap()
+    })
+    .await
+    .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}{}", stdout, String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Resolved 6 distinct hosts with a cache size of 4"), "{}", stdout);
+    assert!(stdout.contains("Evicted: 2 of the first 2 hosts"), "{}", stdout);
+}
@@ -0,0 +1,30 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+mod common;
+use common::{resolver_for, silent_name_server};
+
+#[tokio::test]
+async fn a_timed_out_lookup_stops_querying_and_frees_its_slot() {
+    let (addr, queries) = silent_name_server().await;
+    // Left alone, the lookup would retry twice over 900ms
+    let resolver = resolver_for(addr)
+        .use_hosts_file(true)
+        .timeout(Duration::from_millis(300))
+        .attempts(3)
+        .max_concurrent_lookups(1)
+        .build()
+        .unwrap();
+
+    let slow = tokio::time::timeout(Duration::from_millis(100), resolver.resolve_host("example.com")).await;
+    assert!(slow.is_err(), "the lookup should still be waiting for an answer");
+
+    // Well past the point where retries would have gone out
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+
+    // The only lookup slot is free again; localhost is answered without
+    // asking the name server
+    let next = tokio::time::timeout(Duration::from_millis(100), resolver.resolve_host("localhost")).await;
+    assert!(next.expect("the slot should have been released").is_ok());
+}
@@ -0,0 +1,55 @@
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+mod common;
+use common::{resolver_for, serve_dns, serve_nxdomain, DNS_ANSWER};
+
+#[tokio::test]
+async fn open_circuit_fails_fast_until_the_cooldown_ends() {
+    let (addr, up) = serve_dns(60).await;
+    up.store(false, Ordering::SeqCst);
+    let resolver = resolver_for(addr)
+        .timeout(Duration::from_millis(100))
+        .circuit_breaker(2, Duration::from_millis(500))
+        .build()
+        .unwrap();
+
+    assert!(resolver.resolve_host("example.com").await.is_err());
+    assert!(resolver.resolve_host("example.com").await.is_err());
+
+    // Open now, so even with upstream back the lookup doesn't wait on it
+    up.store(true, Ordering::SeqCst);
+    let start = Instant::now();
+    assert!(resolver.resolve_host("example.com").await.is_err());
+    assert!(start.elapsed() < Duration::from_millis(50));
+
+    // Other hosts are unaffected
+    assert_eq!(resolver.resolve_host("example.org").await.unwrap(), [IpAddr::V4(DNS_ANSWER)]);
+
+    tokio::time::sleep(Duration::from_millis(550)).await;
+    assert_eq!(resolver.resolve_host("example.com").await.unwrap(), [IpAddr::V4(DNS_ANSWER)]);
+}
+
+#[tokio::test]
+async fn a_probe_answered_with_nxdomain_closes_the_circuit() {
+    let (addr, up) = serve_nxdomain().await;
+    up.store(false, Ordering::SeqCst);
+    let resolver = resolver_for(addr)
+        .timeout(Duration::from_millis(100))
+        .circuit_breaker(2, Duration::from_millis(300))
+        .build()
+        .unwrap();
+
+    assert!(resolver.resolve_host("gone.example.com").await.is_err());
+    assert!(resolver.resolve_host("gone.example.com").await.is_err());
+    up.store(true, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(350)).await;
+
+    // The probe gets an answer, if a negative one, so the lookups after it
+    // go through instead of failing fast until the next cooldown
+    for _ in 0..2 {
+        let error = resolver.resolve_host("gone.example.com").await.unwrap_err();
+        assert!(!error.to_string().contains("circuit"), "{}", error);
+    }
+}
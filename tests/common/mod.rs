@@ -0,0 +1,128 @@
+// Each test binary compiles this module but uses only some of its helpers
+#![allow(dead_code)]
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use dns_query_caching::{HickoryDnsResolver, HickoryDnsResolverBuilder, IpStrategy};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, UdpSocket};
+
+/// The address [`serve_dns`] answers every query with.
+pub const DNS_ANSWER: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 9);
+
+// A resolver builder that asks only the name server at `addr`: IPv4 only, so
+// each host takes one query and one cache entry, no hosts file, and a single
+// short attempt so a silent server fails fast. Tests chain their own options
+// on top.
+pub fn resolver_for(addr: SocketAddr) -> HickoryDnsResolverBuilder {
+    HickoryDnsResolver::builder()
+        .name_servers(vec![addr])
+        .ip_strategy(IpStrategy::Ipv4Only)
+        .use_hosts_file(false)
+        .timeout(Duration::from_millis(200))
+        .attempts(1)
+}
+
+// Serves an empty 200 response on localhost, closing every connection so
+// each request has to resolve the host again.
+pub async fn serve_empty() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let _ = stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await;
+        }
+    });
+    port
+}
+
+// Like `serve_empty`, but every response redirects to `location`.
+pub async fn serve_redirect(location: &str) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let response = format!(
+        "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        location
+    );
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+    port
+}
+
+// A name server on localhost that answers every A query with `DNS_ANSWER`
+// and the given TTL, until the returned flag is cleared; then it goes
+// silent, like an unreachable upstream.
+pub async fn serve_dns(ttl: u32) -> (SocketAddr, Arc<AtomicBool>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let up = Arc::new(AtomicBool::new(true));
+    let answering = up.clone();
+    tokio::spawn(async move {
+        let mut buf = [0; 512];
+        while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+            if !answering.load(Ordering::SeqCst) {
+                continue;
+            }
+            // Header: same ID, a response with recursion available, one
+            // question and one answer
+            let mut response = buf[..2].to_vec();
+            response.extend_from_slice(&[0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]);
+            response.extend_from_slice(&buf[12..len]);
+            // The answer: a pointer to the question's name, A, IN, the TTL
+            response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1]);
+            response.extend_from_slice(&ttl.to_be_bytes());
+            response.extend_from_slice(&[0, 4]);
+            response.extend_from_slice(&DNS_ANSWER.octets());
+            socket.send_to(&response, peer).await.unwrap();
+        }
+    });
+    (addr, up)
+}
+
+// Like `serve_dns`, but answers every query with NXDOMAIN while the returned
+// flag is set.
+pub async fn serve_nxdomain() -> (SocketAddr, Arc<AtomicBool>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let up = Arc::new(AtomicBool::new(true));
+    let answering = up.clone();
+    tokio::spawn(async move {
+        let mut buf = [0; 512];
+        while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+            if !answering.load(Ordering::SeqCst) {
+                continue;
+            }
+            // Same ID, a response with recursion available and NXDOMAIN,
+            // echoing the question with no answers
+            let mut response = buf[..2].to_vec();
+            response.extend_from_slice(&[0x81, 0x83, 0, 1, 0, 0, 0, 0, 0, 0]);
+            response.extend_from_slice(&buf[12..len]);
+            socket.send_to(&response, peer).await.unwrap();
+        }
+    });
+    (addr, up)
+}
+
+// A name server on localhost that never answers, counting the queries it
+// receives so retries can be seen.
+pub async fn silent_name_server() -> (SocketAddr, Arc<AtomicUsize>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let queries = Arc::new(AtomicUsize::new(0));
+    let counter = queries.clone();
+    tokio::spawn(async move {
+        let mut buf = [0; 512];
+        while socket.recv_from(&mut buf).await.is_ok() {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+    (addr, queries)
+}
@@ -0,0 +1,86 @@
+use std::error::Error;
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use dns_query_caching::{DnsCacheError, HickoryDnsResolver, IpNet};
+
+mod common;
+use common::serve_redirect;
+
+#[tokio::test]
+async fn private_addresses_are_blocked() {
+    let resolver = HickoryDnsResolver::builder().deny_private(true).build().unwrap();
+    let result = resolver.resolve_host("localhost").await;
+    assert!(
+        matches!(&result, Err(DnsCacheError::Blocked { host, .. }) if host == "localhost"),
+        "{:?}",
+        result
+    );
+}
+
+#[tokio::test]
+async fn denied_addresses_are_dropped_from_mixed_answers() {
+    let path = std::env::temp_dir().join(format!("deny-hosts-{}", std::process::id()));
+    std::fs::File::create(&path)
+        .unwrap()
+        .write_all(b"169.254.169.254 metadata.test mixed.test\n::ffff:169.254.169.254 mapped.test\n192.0.2.1 mixed.test\n")
+        .unwrap();
+    let resolver = HickoryDnsResolver::builder()
+        .extra_hosts(&path)
+        .unwrap()
+        .deny_cidrs(vec!["169.254.0.0/16".parse::<IpNet>().unwrap()])
+        .build()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let ips = resolver.resolve_host("mixed.test").await.unwrap();
+    assert_eq!(ips, ["192.0.2.1".parse::<IpAddr>().unwrap()]);
+    assert!(matches!(
+        resolver.resolve_host("metadata.test").await,
+        Err(DnsCacheError::Blocked { .. })
+    ));
+    // An IPv4-mapped address is no way around an IPv4 range
+    assert!(matches!(
+        resolver.resolve_host("mapped.test").await,
+        Err(DnsCacheError::Blocked { .. })
+    ));
+}
+
+#[tokio::test]
+async fn address_literal_urls_are_refused() {
+    // reqwest never asks the resolver about these, so they need checking
+    // separately
+    let resolver = HickoryDnsResolver::builder().deny_private(true).build().unwrap();
+    for url in [
+        "http://169.254.169.254/latest/meta-data/",
+        "http://127.0.0.1:8080/",
+        "http://[::1]/",
+        "http://[::ffff:10.0.0.1]/",
+    ] {
+        let result = resolver.check_url(&url.parse().unwrap());
+        assert!(matches!(result, Err(DnsCacheError::Blocked { .. })), "{}: {:?}", url, result);
+    }
+    assert!(resolver.check_url(&"http://192.0.2.1/".parse().unwrap()).is_ok());
+    assert!(resolver.check_url(&"http://localhost/".parse().unwrap()).is_ok());
+}
+
+#[tokio::test]
+async fn redirects_to_denied_addresses_are_refused() {
+    let port = serve_redirect("http://127.0.0.2/").await;
+    let resolver = HickoryDnsResolver::builder()
+        .deny_cidrs(vec!["127.0.0.2/32".parse::<IpNet>().unwrap()])
+        .build()
+        .unwrap();
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .redirect(resolver.redirect_policy())
+        .dns_resolver(Arc::new(resolver))
+        .build()
+        .unwrap();
+
+    let error = client.get(format!("http://127.0.0.1:{}/", port)).send().await.unwrap_err();
+    assert!(error.is_redirect(), "{:?}", error);
+    let cause = error.source().and_then(|e| e.downcast_ref::<DnsCacheError>());
+    assert!(matches!(cause, Some(DnsCacheError::Blocked { .. })), "{:?}", error);
+}
@@ -0,0 +1,51 @@
+use std::process::{Command, Output};
+
+// Run the CLI's `resolve localhost`, which needs no network, logging to
+// stdout, with only the given DNS_CACHE_* and RUST_LOG variables set
+fn run(env: &[(&str, &str)], args: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_dns_query_caching"));
+    for var in ["DNS_CACHE_SERVER", "DNS_CACHE_UPSTREAM", "DNS_CACHE_LOG_LEVEL", "DNS_CACHE_URL", "RUST_LOG"] {
+        command.env_remove(var);
+    }
+    command
+        .envs(env.iter().copied())
+        .arg("--log-stdout")
+        .args(args)
+        .args(["resolve", "localhost", "--repeat", "1"])
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn an_upstream_flag_beats_the_dns_server_variable() {
+    let env = [("DNS_CACHE_SERVER", "127.0.0.1:5999")];
+
+    let from_env = stdout(&run(&env, &[]));
+    assert!(from_env.contains("name_servers=127.0.0.1:5999/udp"), "{}", from_env);
+
+    let flag = stdout(&run(&env, &["--upstream", "cloudflare"]));
+    assert!(flag.contains("name_servers=1.1.1.1:53/udp"), "{}", flag);
+    assert!(!flag.contains("5999"), "{}", flag);
+}
+
+#[test]
+fn upstream_and_dns_server_flags_conflict() {
+    let output = run(&[], &["--upstream", "google", "--dns-server", "127.0.0.1:5999"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("can't be used together"));
+}
+
+#[test]
+fn a_log_level_flag_beats_rust_log() {
+    let output = stdout(&run(&[("RUST_LOG", "debug")], &["--log-level", "warn"]));
+    assert!(!output.contains(" INFO ") && !output.contains("DEBUG"), "{}", output);
+
+    // Without the flag, RUST_LOG still beats DNS_CACHE_LOG_LEVEL
+    let output = stdout(&run(&[("RUST_LOG", "warn"), ("DNS_CACHE_LOG_LEVEL", "debug")], &[]));
+    assert!(!output.contains(" INFO "), "{}", output);
+}
@@ -0,0 +1,39 @@
+use std::net::IpAddr;
+
+use dns_query_caching::ResolveOutcome;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+mod common;
+use common::{resolver_for, serve_dns, DNS_ANSWER};
+
+#[tokio::test]
+async fn subscribers_see_each_lookup() {
+    let (addr, _up) = serve_dns(60).await;
+    let resolver = resolver_for(addr).build().unwrap();
+    let mut events = resolver.subscribe();
+
+    assert_eq!(resolver.resolve_host("example.com").await.unwrap(), [IpAddr::V4(DNS_ANSWER)]);
+    resolver.resolve_host("example.com").await.unwrap();
+
+    let miss = events.recv().await.unwrap();
+    assert_eq!((miss.host.as_str(), miss.outcome, miss.addr_count), ("example.com", ResolveOutcome::Miss, 1));
+    let hit = events.recv().await.unwrap();
+    assert_eq!((hit.host.as_str(), hit.outcome, hit.addr_count), ("example.com", ResolveOutcome::Hit, 1));
+    assert_eq!(events.try_recv(), Err(TryRecvError::Empty));
+}
+
+#[tokio::test]
+async fn a_slow_subscriber_misses_the_oldest_events() {
+    let (addr, _up) = serve_dns(60).await;
+    let resolver = resolver_for(addr).event_capacity(2).build().unwrap();
+    let mut events = resolver.subscribe();
+
+    // Nobody reads while these run, and none of them wait on the channel
+    for host in ["a.example.com", "b.example.com", "c.example.com"] {
+        resolver.resolve_host(host).await.unwrap();
+    }
+
+    assert_eq!(events.recv().await, Err(RecvError::Lagged(1)));
+    assert_eq!(events.recv().await.unwrap().host, "b.example.com");
+    assert_eq!(events.recv().await.unwrap().host, "c.example.com");
+}
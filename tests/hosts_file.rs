@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use dns_query_caching::HickoryDnsResolver;
+
+// A single-label name from the OS hosts file other than localhost (which
+// hickory answers itself), if there is one. Single-label names don't exist
+// in public DNS, so it can only resolve through the hosts file.
+fn hosts_file_only_name() -> Option<String> {
+    let hosts = std::fs::read_to_string("/etc/hosts").ok()?;
+    hosts
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split_whitespace().skip(1))
+        .find(|name| !name.contains('.') && !name.eq_ignore_ascii_case("localhost"))
+        .map(str::to_string)
+}
+
+// hickory only reads the OS hosts file, so this can't bring its own; run it
+// with `--ignored` on a machine whose /etc/hosts has such a name
+#[tokio::test]
+#[ignore = "needs a single-label name in /etc/hosts"]
+async fn hosts_file_names_fail_with_hosts_file_disabled() {
+    let name = hosts_file_only_name().expect("no single-label name other than localhost in /etc/hosts");
+
+    let with_hosts = HickoryDnsResolver::builder().build().unwrap();
+    assert!(with_hosts.resolve_host(&name).await.is_ok());
+
+    // Keep the upstream query short; it's expected to fail either way
+    let without_hosts = HickoryDnsResolver::builder()
+        .use_hosts_file(false)
+        .timeout(Duration::from_millis(500))
+        .attempts(1)
+        .build()
+        .unwrap();
+    assert!(without_hosts.resolve_host(&name).await.is_err());
+}
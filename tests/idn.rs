@@ -0,0 +1,31 @@
+use std::io::Write;
+
+use dns_query_caching::{DnsCacheError, HickoryDnsResolver};
+
+#[tokio::test]
+async fn internationalized_hosts_resolve_by_their_punycode_form() {
+    // Mapped under the punycode name only, as in a real hosts file
+    let path = std::env::temp_dir().join(format!("idn-hosts-{}", std::process::id()));
+    std::fs::File::create(&path)
+        .unwrap()
+        .write_all(b"127.0.0.7 xn--mnchen-3ya.de\n")
+        .unwrap();
+    let resolver = HickoryDnsResolver::builder().extra_hosts(&path).unwrap().build().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let ips = resolver.resolve_host("münchen.de").await.unwrap();
+    assert_eq!(ips, ["127.0.0.7".parse::<std::net::IpAddr>().unwrap()]);
+    let chain = resolver.trace_cname("münchen.de").await.unwrap();
+    assert_eq!(chain, ["xn--mnchen-3ya.de"]);
+}
+
+#[tokio::test]
+async fn invalid_internationalized_hosts_fail_before_any_lookup() {
+    let resolver = HickoryDnsResolver::new();
+    let result = resolver.resolve_host("xn--münchen.de").await;
+    assert!(
+        matches!(&result, Err(DnsCacheError::InvalidHostname { host }) if host == "xn--münchen.de"),
+        "{:?}",
+        result
+    );
+}
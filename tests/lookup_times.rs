@@ -0,0 +1,19 @@
+use dns_query_caching::{with_lookup_times, HickoryDnsResolver};
+use futures::future::join_all;
+
+#[tokio::test]
+async fn concurrent_scopes_each_see_their_own_lookup() {
+    let resolver = HickoryDnsResolver::new();
+
+    // Every scope resolves the same host at once; each must get its own
+    // lookup rather than racing the others for one shared entry
+    let scopes = (0..8).map(|_| with_lookup_times(resolver.resolve_host("localhost")));
+    for (resolved, times) in join_all(scopes).await {
+        resolved.unwrap();
+        assert_eq!(times.keys().collect::<Vec<_>>(), ["localhost"]);
+    }
+
+    // A scope without lookups records nothing
+    let ((), times) = with_lookup_times(async {}).await;
+    assert!(times.is_empty());
+}
@@ -0,0 +1,38 @@
+use std::sync::{Arc, Mutex};
+
+use dns_query_caching::HickoryDnsResolver;
+
+#[tokio::test]
+async fn the_hook_sees_every_successful_resolution() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let record = seen.clone();
+    let resolver = HickoryDnsResolver::builder()
+        .on_resolve(move |host, ips| record.lock().unwrap().push((host.to_string(), ips.to_vec())))
+        .build()
+        .unwrap();
+
+    // localhost comes from the hosts file, so no network is needed; the
+    // second lookup is a cache hit
+    let first = resolver.resolve_host("localhost").await.unwrap();
+    let second = resolver.resolve_host("localhost").await.unwrap();
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(*seen, [("localhost".to_string(), first), ("localhost".to_string(), second)]);
+}
+
+#[tokio::test]
+async fn the_hook_sees_ttl_and_uncached_resolutions() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let record = seen.clone();
+    let resolver = HickoryDnsResolver::builder()
+        .on_resolve(move |host, ips| record.lock().unwrap().push((host.to_string(), ips.to_vec())))
+        .build()
+        .unwrap();
+
+    let with_ttl = resolver.resolve_host_with_ttl("localhost").await.unwrap();
+    let fresh = resolver.resolve_host_fresh("localhost").await.unwrap();
+
+    let with_ttl: Vec<_> = with_ttl.into_iter().map(|(ip, _)| ip).collect();
+    let seen = seen.lock().unwrap();
+    assert_eq!(*seen, [("localhost".to_string(), with_ttl), ("localhost".to_string(), fresh)]);
+}
@@ -0,0 +1,60 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use dns_query_caching::HickoryDnsResolver;
+
+mod common;
+use common::serve_empty;
+
+#[tokio::test]
+async fn only_the_proxy_host_goes_through_the_resolver() {
+    let port = serve_empty().await;
+    let proxy = format!("http://localhost:{}", port);
+
+    let resolver = HickoryDnsResolver::shared();
+    let client = reqwest::Client::builder()
+        .dns_resolver(Arc::clone(&resolver))
+        .proxy(reqwest::Proxy::all(&proxy).unwrap())
+        .build()
+        .unwrap();
+
+    // The target doesn't exist; it's only the proxy that would resolve it
+    let response = client.get("http://target.invalid/").send().await.unwrap();
+    assert!(response.status().is_success());
+
+    let hosts: Vec<String> = resolver.cached_entries().into_iter().map(|e| e.host).collect();
+    assert_eq!(hosts, ["localhost"]);
+}
+
+#[tokio::test]
+async fn a_proxy_hostname_is_resolved_by_our_resolver() {
+    let port = serve_empty().await;
+    // The proxy's name only exists in this mapping, so the request can only
+    // reach it if reqwest asked our resolver
+    let path = std::env::temp_dir().join(format!("proxy-hosts-{}", std::process::id()));
+    std::fs::File::create(&path)
+        .unwrap()
+        .write_all(b"127.0.0.1 proxy.test\n")
+        .unwrap();
+    let resolved = Arc::new(Mutex::new(Vec::new()));
+    let record = resolved.clone();
+    let resolver = HickoryDnsResolver::builder()
+        .extra_hosts(&path)
+        .unwrap()
+        .use_hosts_file(false)
+        .on_resolve(move |host, ips| record.lock().unwrap().push((host.to_string(), ips.to_vec())))
+        .build()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let client = reqwest::Client::builder()
+        .dns_resolver(Arc::new(resolver))
+        .proxy(reqwest::Proxy::all(format!("http://proxy.test:{}", port)).unwrap())
+        .build()
+        .unwrap();
+    let response = client.get("http://target.invalid/").send().await.unwrap();
+    assert!(response.status().is_success());
+
+    let resolved = resolved.lock().unwrap();
+    assert_eq!(*resolved, [("proxy.test".to_string(), vec!["127.0.0.1".parse().unwrap()])]);
+}
@@ -0,0 +1,27 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use dns_query_caching::HickoryDnsResolver;
+
+mod common;
+use common::{resolver_for, serve_dns, DNS_ANSWER};
+
+fn resolver(name_server: SocketAddr, serve_stale: Duration) -> HickoryDnsResolver {
+    resolver_for(name_server).serve_stale(serve_stale).build().unwrap()
+}
+
+#[tokio::test]
+async fn expired_answers_are_served_while_upstream_is_down() {
+    let (addr, up) = serve_dns(1).await;
+    let with_stale = resolver(addr, Duration::from_secs(60));
+    let without_stale = resolver(addr, Duration::ZERO);
+    assert_eq!(with_stale.resolve_host("example.com").await.unwrap(), [IpAddr::V4(DNS_ANSWER)]);
+    assert_eq!(without_stale.resolve_host("example.com").await.unwrap(), [IpAddr::V4(DNS_ANSWER)]);
+
+    up.store(false, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    assert_eq!(with_stale.resolve_host("example.com").await.unwrap(), [IpAddr::V4(DNS_ANSWER)]);
+    assert!(without_stale.resolve_host("example.com").await.is_err());
+}
@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use dns_query_caching::HickoryDnsResolver;
+
+mod common;
+use common::serve_empty;
+
+#[tokio::test]
+async fn clients_sharing_a_resolver_share_its_cache() {
+    let port = serve_empty().await;
+    let url = format!("http://localhost:{}/", port);
+
+    // localhost comes from the hosts file, which hickory caches like any
+    // other answer, so this doesn't need network access
+    let resolver = HickoryDnsResolver::shared();
+    let first = reqwest::Client::builder()
+        .no_proxy()
+        .dns_resolver(Arc::clone(&resolver))
+        .build()
+        .unwrap();
+    let second = reqwest::Client::builder()
+        .no_proxy()
+        .dns_resolver(Arc::clone(&resolver))
+        .build()
+        .unwrap();
+
+    first.get(&url).send().await.unwrap();
+    let stats = resolver.stats();
+    assert_eq!((stats.hits, stats.misses), (0, 1));
+
+    second.get(&url).send().await.unwrap();
+    let stats = resolver.stats();
+    assert_eq!((stats.hits, stats.misses), (1, 1));
+}
@@ -0,0 +1,21 @@
+#![cfg(feature = "test-util")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use dns_query_caching::testing::StaticResolver;
+use dns_query_caching::with_lookup_times;
+
+mod common;
+use common::serve_empty;
+
+#[tokio::test]
+async fn answers_are_recorded_for_with_lookup_times() {
+    let port = serve_empty().await;
+    let resolver = StaticResolver::default().with_host("static.test", vec!["127.0.0.1".parse().unwrap()]);
+    let client = reqwest::Client::builder().no_proxy().dns_resolver(Arc::new(resolver)).build().unwrap();
+
+    let (response, times) = with_lookup_times(client.get(format!("http://static.test:{}/", port)).send()).await;
+    assert!(response.unwrap().status().is_success());
+    assert_eq!(times.get("static.test"), Some(&Duration::ZERO));
+}
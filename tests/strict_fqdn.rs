@@ -0,0 +1,30 @@
+use std::net::IpAddr;
+
+use dns_query_caching::{CacheStats, DnsCacheError, HickoryDnsResolver};
+
+#[tokio::test]
+async fn relative_names_are_rejected_before_any_lookup() {
+    let resolver = HickoryDnsResolver::builder().strict_fqdn(true).build().unwrap();
+
+    for name in ["localhost", "db.internal1", "example..com", ".com"] {
+        let result = resolver.resolve_host(name).await;
+        assert!(
+            matches!(&result, Err(DnsCacheError::NotFullyQualified { host }) if host == name),
+            "{}: {:?}",
+            name,
+            result
+        );
+    }
+    assert!(matches!(
+        resolver.trace_cname("localhost").await,
+        Err(DnsCacheError::NotFullyQualified { .. })
+    ));
+    assert_eq!(resolver.stats(), CacheStats::default());
+}
+
+#[tokio::test]
+async fn ip_addresses_pass_strict_mode() {
+    let resolver = HickoryDnsResolver::builder().strict_fqdn(true).build().unwrap();
+    let ips = resolver.resolve_host("127.0.0.1").await.unwrap();
+    assert_eq!(ips, ["127.0.0.1".parse::<IpAddr>().unwrap()]);
+}
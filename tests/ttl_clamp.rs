@@ -0,0 +1,28 @@
+use std::time::Duration;
+
+mod common;
+use common::{resolver_for, serve_dns};
+
+#[tokio::test]
+async fn ttls_below_the_floor_are_extended() {
+    let (addr, _) = serve_dns(1).await;
+    let resolver = resolver_for(addr).min_ttl(Duration::from_secs(60)).build().unwrap();
+
+    resolver.resolve_host("example.com").await.unwrap();
+
+    let entries = resolver.cached_entries();
+    assert_eq!(entries.len(), 1);
+    let remaining = entries[0].ttl_remaining();
+    assert!(remaining > Duration::from_secs(55), "{:?}", remaining);
+}
+
+#[tokio::test]
+async fn ttls_above_the_ceiling_are_cut() {
+    let (addr, _) = serve_dns(3600).await;
+    let resolver = resolver_for(addr).max_ttl(Duration::from_secs(60)).build().unwrap();
+
+    resolver.resolve_host("example.com").await.unwrap();
+
+    let remaining = resolver.cached_entries()[0].ttl_remaining();
+    assert!(remaining <= Duration::from_secs(60), "{:?}", remaining);
+}